@@ -1,17 +1,98 @@
 use std::any::type_name;
 use std::cmp::Ordering;
-use std::fmt::Debug;
+use std::fmt::{self, Debug, Formatter};
+use std::marker::PhantomData;
+use std::sync::atomic::{AtomicU32, Ordering as AtomicOrdering};
 
-use bincode::{config, Decode, Encode};
+use bincode::{
+    config,
+    error::{DecodeError, EncodeError},
+    Decode, Encode,
+};
 use redb::{Key, TypeName, Value};
 use serde::{de::DeserializeOwned, Deserialize, Serialize};
 
+use crate::error::CakeError;
+
 /// Wrapper type to handle keys and values using bincode serialization.
 ///
 /// Wrap your types in this when creating your `TableDefinition`s.
 #[derive(Debug)]
 pub struct Bincode<T>(pub T);
 
+/// Default for [`decode_limit`]: 16 MiB.
+pub const DEFAULT_DECODE_LIMIT: usize = 16 << 20;
+
+/// Largest limit [`set_decode_limit`] accepts: 1 GiB.
+pub const MAX_DECODE_LIMIT: usize = 1 << 30;
+
+/// Base-2 logarithm of the current [`decode_limit`].
+static DECODE_LIMIT_LOG2: AtomicU32 = AtomicU32::new(DEFAULT_DECODE_LIMIT.ilog2());
+
+/// Returns the maximum number of bytes a single key or value may claim while being decoded.
+///
+/// Length prefixes claiming more than this, e.g. from a corrupted or forged database file,
+/// are rejected instead of attempting a huge allocation. Defaults to [`DEFAULT_DECODE_LIMIT`].
+pub fn decode_limit() -> usize {
+    1 << DECODE_LIMIT_LOG2.load(AtomicOrdering::Relaxed)
+}
+
+/// Sets the [`decode_limit`] to at least `bytes`.
+///
+/// `redb` decodes keys and values without knowing which database they come from, so the limit
+/// applies to the whole process. `bincode` only takes limits as const parameters, so `bytes` is
+/// rounded up to a power of two, and to no less than 1 KiB.
+/// Returns an error if `bytes` is above [`MAX_DECODE_LIMIT`].
+pub fn set_decode_limit(bytes: usize) -> Result<(), CakeError> {
+    if bytes > MAX_DECODE_LIMIT {
+        return Err(CakeError::InvalidArgument(format!(
+            "decode limit of {bytes} bytes is above the maximum of {MAX_DECODE_LIMIT}"
+        )));
+    }
+
+    let log2 = bytes.max(1 << 10).next_power_of_two().ilog2();
+    DECODE_LIMIT_LOG2.store(log2, AtomicOrdering::Relaxed);
+
+    Ok(())
+}
+
+impl<T> Bincode<T>
+where
+    T: Decode<()>,
+{
+    /// Decodes a value from its bytes, returning an error instead of panicking on invalid data.
+    ///
+    /// Decoding is bounded by [`decode_limit`].
+    pub fn try_from_bytes(data: &[u8]) -> Result<T, DecodeError> {
+        macro_rules! decode_with_limit {
+            ($($log2:literal)*) => {
+                match DECODE_LIMIT_LOG2.load(AtomicOrdering::Relaxed) {
+                    $(
+                        $log2 => bincode::decode_from_slice(
+                            data,
+                            config::standard().with_limit::<{ 1 << $log2 }>(),
+                        ),
+                    )*
+                    log2 => unreachable!("decode limit of 2^{log2} bytes is out of range"),
+                }
+            };
+        }
+
+        decode_with_limit!(10 11 12 13 14 15 16 17 18 19 20 21 22 23 24 25 26 27 28 29 30)
+            .map(|(value, _)| value)
+    }
+}
+
+impl<T> Bincode<T>
+where
+    T: Encode,
+{
+    /// Encodes a value into its bytes, returning an error instead of panicking if it can't be encoded.
+    pub fn try_as_bytes(value: &T) -> Result<Vec<u8>, EncodeError> {
+        bincode::encode_to_vec(value, config::standard())
+    }
+}
+
 impl<T> Value for Bincode<T>
 where
     T: Debug + Serialize + for<'a> Deserialize<'a> + Decode<()> + Encode,
@@ -34,17 +115,14 @@ where
     where
         Self: 'a,
     {
-        bincode::decode_from_slice(data, config::standard())
-            .expect("failed to deserialize bincode value")
-            .0
+        Self::try_from_bytes(data).expect("failed to deserialize bincode value")
     }
 
     fn as_bytes<'a, 'b: 'a>(value: &'a Self::SelfType<'b>) -> Self::AsBytes<'a>
     where
         Self: 'a + 'b,
     {
-        bincode::encode_to_vec(value, config::standard())
-            .expect("failed to serialize bincode value")
+        Self::try_as_bytes(value).expect("failed to serialize bincode value")
     }
 
     fn type_name() -> TypeName {
@@ -60,3 +138,61 @@ where
         Self::from_bytes(data1).cmp(&Self::from_bytes(data2))
     }
 }
+
+/// Raw view over the bytes stored for a [`Bincode<T>`].
+///
+/// It shares the type name of `Bincode<T>`, so a table defined with `Bincode` types can be
+/// opened with `RawBincode` types to read the stored bytes without decoding them.
+pub(crate) struct RawBincode<T>(PhantomData<T>);
+
+impl<T> Debug for RawBincode<T> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "RawBincode<{}>", type_name::<T>())
+    }
+}
+
+impl<T> Value for RawBincode<T>
+where
+    Bincode<T>: Value,
+{
+    type SelfType<'a>
+        = &'a [u8]
+    where
+        Self: 'a;
+
+    type AsBytes<'a>
+        = &'a [u8]
+    where
+        Self: 'a;
+
+    fn fixed_width() -> Option<usize> {
+        None
+    }
+
+    fn from_bytes<'a>(data: &'a [u8]) -> Self::SelfType<'a>
+    where
+        Self: 'a,
+    {
+        data
+    }
+
+    fn as_bytes<'a, 'b: 'a>(value: &'a Self::SelfType<'b>) -> Self::AsBytes<'a>
+    where
+        Self: 'a + 'b,
+    {
+        value
+    }
+
+    fn type_name() -> TypeName {
+        Bincode::<T>::type_name()
+    }
+}
+
+impl<T> Key for RawBincode<T>
+where
+    Bincode<T>: Key,
+{
+    fn compare(data1: &[u8], data2: &[u8]) -> Ordering {
+        Bincode::<T>::compare(data1, data2)
+    }
+}
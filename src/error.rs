@@ -0,0 +1,200 @@
+use std::fmt;
+
+/// Errors returned by [`CakeDb`](crate::CakeDb) operations.
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum CakeError {
+    /// The database file couldn't be opened or created.
+    Database(redb::DatabaseError),
+    /// A transaction couldn't be started.
+    Transaction(redb::TransactionError),
+    /// A table couldn't be opened, e.g. because it was created with different key or value types.
+    Table(redb::TableError),
+    /// The underlying storage failed.
+    Storage(redb::StorageError),
+    /// A transaction couldn't be committed.
+    Commit(redb::CommitError),
+    /// A savepoint couldn't be taken or restored.
+    Savepoint(redb::SavepointError),
+    /// A file operation failed, e.g. while copying a checkpoint.
+    Io(std::io::Error),
+    /// The key isn't present in the table.
+    KeyNotFound,
+    /// A key or value couldn't be encoded or decoded.
+    Serialization(String),
+    /// No savepoint is stored under the given key.
+    SavepointNotFound(usize),
+    /// No checkpoint with the given name exists.
+    CheckpointNotFound(String),
+    /// The same key appeared twice in an input that requires unique keys.
+    DuplicateInputKey(String),
+    /// A table was registered again with different key or value types.
+    TableTypeMismatch {
+        /// Name of the table.
+        table: String,
+        /// Key and value type names the table was first registered with.
+        registered: (String, String),
+        /// Key and value type names of the conflicting registration.
+        found: (String, String),
+    },
+    /// Compaction couldn't run, e.g. because savepoints are active.
+    CompactionBlocked {
+        /// What blocked it, and how to unblock it.
+        reason: String,
+    },
+    /// A range's bounds are reversed.
+    InvalidRange(String),
+    /// An argument is outside the accepted values, e.g. a chunk size of zero.
+    InvalidArgument(String),
+    /// There's no key left after the last one to [`push`](crate::CakeDb::push) to.
+    KeySpaceExhausted,
+}
+
+impl fmt::Display for CakeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Database(e) => write!(f, "database error: {e}"),
+            Self::Transaction(e) => write!(f, "transaction error: {e}"),
+            Self::Table(e) => write!(f, "table error: {e}"),
+            Self::Storage(e) => write!(f, "storage error: {e}"),
+            Self::Commit(e) => write!(f, "commit error: {e}"),
+            Self::Savepoint(e) => write!(f, "savepoint error: {e}"),
+            Self::Io(e) => write!(f, "io error: {e}"),
+            Self::KeyNotFound => write!(f, "key not found in table"),
+            Self::Serialization(e) => write!(f, "serialization error: {e}"),
+            Self::SavepointNotFound(key) => write!(f, "failed to get specified savepoint: {key}"),
+            Self::CheckpointNotFound(name) => write!(f, "failed to find checkpoint: {name}"),
+            Self::DuplicateInputKey(key) => write!(f, "duplicate input key: {key}"),
+            Self::TableTypeMismatch {
+                table,
+                registered,
+                found,
+            } => write!(
+                f,
+                "table type mismatch for '{table}': registered as {registered:?}, got {found:?}"
+            ),
+            Self::CompactionBlocked { reason } => write!(f, "compaction blocked: {reason}"),
+            Self::InvalidRange(e) => write!(f, "invalid range: {e}"),
+            Self::InvalidArgument(e) => write!(f, "invalid argument: {e}"),
+            Self::KeySpaceExhausted => write!(f, "key space exhausted"),
+        }
+    }
+}
+
+impl CakeError {
+    /// Returns the name of the variant, e.g. `"KeyNotFound"`, as a stable identifier for the error.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Self::Database(_) => "Database",
+            Self::Transaction(_) => "Transaction",
+            Self::Table(_) => "Table",
+            Self::Storage(_) => "Storage",
+            Self::Commit(_) => "Commit",
+            Self::Savepoint(_) => "Savepoint",
+            Self::Io(_) => "Io",
+            Self::KeyNotFound => "KeyNotFound",
+            Self::Serialization(_) => "Serialization",
+            Self::SavepointNotFound(_) => "SavepointNotFound",
+            Self::CheckpointNotFound(_) => "CheckpointNotFound",
+            Self::DuplicateInputKey(_) => "DuplicateInputKey",
+            Self::TableTypeMismatch { .. } => "TableTypeMismatch",
+            Self::CompactionBlocked { .. } => "CompactionBlocked",
+            Self::InvalidRange(_) => "InvalidRange",
+            Self::InvalidArgument(_) => "InvalidArgument",
+            Self::KeySpaceExhausted => "KeySpaceExhausted",
+        }
+    }
+}
+
+/// Serializes as `{ "kind", "message" }`, with the variant name and the [`Display`](fmt::Display)
+/// message. Keys are left out of the message, since they may hold sensitive data.
+#[cfg(feature = "serde")]
+impl serde::Serialize for CakeError {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        use serde::ser::SerializeStruct;
+
+        let message = match self {
+            Self::DuplicateInputKey(_) => "duplicate input key".to_string(),
+            e => e.to_string(),
+        };
+
+        let mut state = serializer.serialize_struct("CakeError", 2)?;
+        state.serialize_field("kind", self.kind())?;
+        state.serialize_field("message", &message)?;
+        state.end()
+    }
+}
+
+impl std::error::Error for CakeError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Database(e) => Some(e),
+            Self::Transaction(e) => Some(e),
+            Self::Table(e) => Some(e),
+            Self::Storage(e) => Some(e),
+            Self::Commit(e) => Some(e),
+            Self::Savepoint(e) => Some(e),
+            Self::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+macro_rules! impl_from {
+    ($($source:ty => $variant:ident),* $(,)?) => {
+        $(
+            impl From<$source> for CakeError {
+                fn from(e: $source) -> Self {
+                    Self::$variant(e)
+                }
+            }
+        )*
+    };
+}
+
+impl_from! {
+    redb::DatabaseError => Database,
+    redb::TransactionError => Transaction,
+    redb::TableError => Table,
+    redb::StorageError => Storage,
+    redb::CommitError => Commit,
+    redb::SavepointError => Savepoint,
+    std::io::Error => Io,
+}
+
+impl From<redb::CompactionError> for CakeError {
+    fn from(e: redb::CompactionError) -> Self {
+        match e {
+            redb::CompactionError::Storage(e) => Self::Storage(e),
+            e => Self::CompactionBlocked {
+                reason: e.to_string(),
+            },
+        }
+    }
+}
+
+impl From<bincode::error::EncodeError> for CakeError {
+    fn from(e: bincode::error::EncodeError) -> Self {
+        Self::Serialization(e.to_string())
+    }
+}
+
+impl From<bincode::error::DecodeError> for CakeError {
+    fn from(e: bincode::error::DecodeError) -> Self {
+        Self::Serialization(e.to_string())
+    }
+}
+
+#[cfg(feature = "json")]
+impl From<serde_json::Error> for CakeError {
+    fn from(e: serde_json::Error) -> Self {
+        Self::Serialization(e.to_string())
+    }
+}
+
+#[cfg(feature = "json")]
+impl From<base64::DecodeError> for CakeError {
+    fn from(e: base64::DecodeError) -> Self {
+        Self::Serialization(e.to_string())
+    }
+}
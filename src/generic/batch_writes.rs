@@ -1,31 +1,223 @@
-use redb::{ReadableTable, TableDefinition};
+use std::{
+    collections::{BTreeMap, BTreeSet, VecDeque},
+    num::NonZeroU64,
+};
 
-use crate::{bincode_wrapper::Bincode, CakeDb};
+use redb::{ReadableTable, ReadableTableMetadata, TableDefinition, TableHandle, WriteTransaction};
 
-use super::traits::{DbKey, DbValue};
+use crate::{
+    bincode_wrapper::{Bincode, RawBincode},
+    error::CakeError,
+    CakeDb,
+};
+
+use super::{
+    internal::encode_pair,
+    traits::{DbKey, DbValue},
+};
 
 impl CakeDb {
     /// Inserts all key-value pairs into the given table.
     ///
     /// Overwrites any values whose keys were already present.
+    ///
+    /// Every pair is serialized before the transaction starts, so if any of them can't be,
+    /// an `Err` is returned and nothing is inserted.
     pub fn batch_insert<K, V, I>(
         &mut self,
         table_def: TableDefinition<Bincode<K>, Bincode<V>>,
         data: I,
-    ) -> Result<(), Box<dyn std::error::Error>>
+    ) -> Result<(), CakeError>
+    where
+        K: DbKey,
+        V: DbValue,
+        I: IntoIterator<Item = (K, V)>,
+    {
+        let pairs = data
+            .into_iter()
+            .map(|(key, value)| encode_pair(&key, &value))
+            .collect::<Result<Vec<_>, _>>()?;
+        let raw_def: TableDefinition<RawBincode<K>, RawBincode<V>> =
+            TableDefinition::new(table_def.name());
+
+        let transaction = self.inner.begin_write()?;
+        {
+            let mut table = transaction.open_table(raw_def)?;
+
+            for (key, value) in &pairs {
+                table.insert(key.as_slice(), value.as_slice())?;
+            }
+        }
+        self.record_write(&transaction, table_def.name())?;
+        transaction.commit()?;
+
+        Ok(())
+    }
+
+    /// Inserts all key-value pairs into the given table, like [`batch_insert`](Self::batch_insert).
+    ///
+    /// Returns the values that were overwritten, keyed by their keys. Keys that weren't in the table
+    /// beforehand don't appear, even if they're repeated in `data`.
+    pub fn batch_replace<K, V, I>(
+        &mut self,
+        table_def: TableDefinition<Bincode<K>, Bincode<V>>,
+        data: I,
+    ) -> Result<BTreeMap<K, V>, CakeError>
+    where
+        K: DbKey,
+        V: DbValue,
+        I: IntoIterator<Item = (K, V)>,
+    {
+        let pairs = data
+            .into_iter()
+            .map(|(key, value)| Ok((encode_pair(&key, &value)?, key)))
+            .collect::<Result<Vec<_>, CakeError>>()?;
+        let raw_def: TableDefinition<RawBincode<K>, RawBincode<V>> =
+            TableDefinition::new(table_def.name());
+        let mut replaced = BTreeMap::new();
+
+        let transaction = self.inner.begin_write()?;
+        {
+            let mut table = transaction.open_table(raw_def)?;
+
+            let mut seen = BTreeSet::new();
+            for ((raw_key, raw_value), key) in pairs {
+                let old = match table.insert(raw_key.as_slice(), raw_value.as_slice())? {
+                    Some(guard) => Some(Bincode::<V>::try_from_bytes(guard.value())?),
+                    None => None,
+                };
+                if let (Some(old), true) = (old, seen.insert(raw_key)) {
+                    replaced.insert(key, old);
+                }
+            }
+        }
+        self.record_write(&transaction, table_def.name())?;
+        transaction.commit()?;
+
+        Ok(replaced)
+    }
+
+    /// Applies each key's own `edit` closure to its value, in a single transaction.
+    ///
+    /// Keys that aren't present in the table are skipped.
+    ///
+    /// Returns the number of edited values.
+    pub fn update_many<K, V, I>(
+        &mut self,
+        table_def: TableDefinition<Bincode<K>, Bincode<V>>,
+        edits: I,
+    ) -> Result<u64, CakeError>
+    where
+        K: DbKey,
+        V: DbValue,
+        I: IntoIterator<Item = (K, Box<dyn FnOnce(&mut V)>)>,
+    {
+        let mut edited_count = 0;
+
+        let transaction = self.inner.begin_write()?;
+        {
+            let mut table = transaction.open_table(table_def)?;
+
+            for (key, edit) in edits {
+                let mut edited: V;
+                {
+                    let Some(value) = table.get(&key)? else {
+                        continue;
+                    };
+                    edited = value.value();
+                    edit(&mut edited)
+                };
+
+                table.insert(&key, edited)?;
+                edited_count += 1;
+            }
+        }
+        self.record_write(&transaction, table_def.name())?;
+        transaction.commit()?;
+
+        Ok(edited_count)
+    }
+
+    /// Replaces the contents of the given table with the key-value pairs of `map`.
+    ///
+    /// The table is cleared and refilled in a single transaction. This is the inverse of
+    /// [`table`](Self::table): loading a map and then reading the table back returns an equal map.
+    pub fn load_from_map<K, V>(
+        &mut self,
+        table_def: TableDefinition<Bincode<K>, Bincode<V>>,
+        map: BTreeMap<K, V>,
+    ) -> Result<(), CakeError>
+    where
+        K: DbKey,
+        V: DbValue,
+    {
+        self.replace_table(table_def, map)?;
+
+        Ok(())
+    }
+
+    /// Atomically replaces the contents of the given table with `data`.
+    ///
+    /// The table is cleared and refilled in a single transaction, so readers see
+    /// either the old or the new contents, never a partially populated table.
+    ///
+    /// Returns the number of inserted pairs.
+    pub fn replace_table<K, V, I>(
+        &mut self,
+        table_def: TableDefinition<Bincode<K>, Bincode<V>>,
+        data: I,
+    ) -> Result<u64, CakeError>
     where
         K: DbKey,
         V: DbValue,
         I: IntoIterator<Item = (K, V)>,
     {
+        let mut inserted = 0;
+
         let transaction = self.inner.begin_write()?;
         {
             let mut table = transaction.open_table(table_def)?;
+            table.retain(|_, _| false)?;
 
             for (key, value) in data {
                 table.insert(&key, value)?;
+                inserted += 1;
             }
         }
+        self.record_write(&transaction, table_def.name())?;
+        transaction.commit()?;
+
+        Ok(inserted)
+    }
+
+    /// Inserts all key-value pairs into the given table, rejecting input with repeated keys.
+    ///
+    /// Keys already present in the table are overwritten as in [`batch_insert`](Self::batch_insert),
+    /// but if the same key appears twice in `data`, returns an error and nothing is written.
+    pub fn batch_insert_unique<K, V, I>(
+        &mut self,
+        table_def: TableDefinition<Bincode<K>, Bincode<V>>,
+        data: I,
+    ) -> Result<(), CakeError>
+    where
+        K: DbKey,
+        V: DbValue,
+        I: IntoIterator<Item = (K, V)>,
+    {
+        let transaction = self.inner.begin_write()?;
+        {
+            let mut table = transaction.open_table(table_def)?;
+
+            let mut seen = BTreeSet::new();
+            for (key, value) in data {
+                if seen.contains(&key) {
+                    return Err(CakeError::DuplicateInputKey(format!("{key:?}")));
+                }
+                table.insert(&key, value)?;
+                seen.insert(key);
+            }
+        }
+        self.record_write(&transaction, table_def.name())?;
         transaction.commit()?;
 
         Ok(())
@@ -37,9 +229,9 @@ impl CakeDb {
         table_def: TableDefinition<Bincode<K>, Bincode<V>>,
         keys: I,
         edit: impl Fn(&K, &mut V),
-    ) -> Result<(), Box<dyn std::error::Error>>
+    ) -> Result<(), CakeError>
     where
-        K: DbKey + Clone,
+        K: DbKey,
         V: DbValue,
         I: IntoIterator<Item = &'a K>,
     {
@@ -60,25 +252,172 @@ impl CakeDb {
                 table.insert(key, edited)?;
             }
         }
+        self.record_write(&transaction, table_def.name())?;
         transaction.commit()?;
 
         Ok(())
     }
 
+    /// Removes all key-value pairs that match the given predicate, and returns them.
+    pub fn remove_where<K, V>(
+        &mut self,
+        table_def: TableDefinition<Bincode<K>, Bincode<V>>,
+        predicate: impl Fn(&K, &V) -> bool,
+    ) -> Result<BTreeMap<K, V>, CakeError>
+    where
+        K: DbKey,
+        V: DbValue,
+    {
+        let removed: BTreeMap<K, V>;
+
+        let transaction = self.inner.begin_write()?;
+        {
+            let mut table = transaction.open_table(table_def)?;
+            removed = table
+                .extract_if(|k, v| predicate(&k, &v))?
+                .map(|pair| pair.map(|(kg, vg)| (kg.value(), vg.value())))
+                .collect::<Result<_, _>>()?;
+        }
+        self.record_write(&transaction, table_def.name())?;
+        transaction.commit()?;
+
+        Ok(removed)
+    }
+
+    /// Copies every pair of `from` into `to`, re-keying each with `f`, and returns how many were copied.
+    ///
+    /// Runs in a single write transaction, and `from` is left untouched. If `f` maps several keys
+    /// to the same new key, the last one in `from`'s key order wins. `from` and `to` must be
+    /// different tables.
+    pub fn map_keys<K1, V, K2>(
+        &mut self,
+        from: TableDefinition<Bincode<K1>, Bincode<V>>,
+        to: TableDefinition<Bincode<K2>, Bincode<V>>,
+        f: impl Fn(&K1) -> K2,
+    ) -> Result<u64, CakeError>
+    where
+        K1: DbKey,
+        K2: DbKey,
+        V: DbValue,
+    {
+        let mut copied = 0;
+
+        let transaction = self.inner.begin_write()?;
+        {
+            let source = transaction.open_table(from)?;
+            let mut target = transaction.open_table(to)?;
+
+            for pair in source.iter()? {
+                let (kg, vg) = pair?;
+                target.insert(f(&kg.value()), vg.value())?;
+                copied += 1;
+            }
+        }
+        self.record_write(&transaction, to.name())?;
+        transaction.commit()?;
+
+        Ok(copied)
+    }
+
+    /// Removes pairs in ascending key order until the table stores at most `max_bytes` of keys and values.
+    ///
+    /// There's no access metadata to evict by, so the lowest keys are always evicted first.
+    /// Only the encoded keys and values count towards the budget, not `redb`'s page overhead.
+    /// Returns how many pairs were removed.
+    pub fn prune_to_byte_budget<K, V>(
+        &mut self,
+        table_def: TableDefinition<Bincode<K>, Bincode<V>>,
+        max_bytes: u64,
+    ) -> Result<u64, CakeError>
+    where
+        K: DbKey,
+        V: DbValue,
+    {
+        let transaction = self.inner.begin_write()?;
+        let removed = prune_raw_table::<K, V>(&transaction, table_def.name(), max_bytes)?;
+        if removed > 0 {
+            self.record_write(&transaction, table_def.name())?;
+        }
+        transaction.commit()?;
+
+        Ok(removed)
+    }
+
+    /// Keeps the table within `max_bytes` of keys and values automatically.
+    ///
+    /// Every `check_interval`-th committed write to the table also
+    /// [prunes it](Self::prune_to_byte_budget) within the same transaction, evicting the lowest
+    /// keys first. Writes made directly through [`database`](Self::database) aren't counted.
+    /// Setting a new budget for the same table replaces the previous one.
+    pub fn set_table_budget<K, V>(
+        &mut self,
+        table_def: TableDefinition<Bincode<K>, Bincode<V>>,
+        max_bytes: u64,
+        check_interval: NonZeroU64,
+    ) where
+        K: DbKey + 'static,
+        V: DbValue + 'static,
+    {
+        let name = table_def.name().to_string();
+        let budget = TableBudget {
+            check_interval,
+            prune: Box::new({
+                let name = name.clone();
+                move |transaction| prune_raw_table::<K, V>(transaction, &name, max_bytes)
+            }),
+        };
+        self.table_budgets.insert(name, budget);
+    }
+
+    /// Returns an iterator that yields every key-value pair in the table while removing it.
+    ///
+    /// Pairs are read in chunks of [`DRAIN_CHUNK_SIZE`], so memory use stays bounded regardless
+    /// of the size of the table. Yielded pairs are removed in one transaction per chunk, which is
+    /// committed right before the next chunk is read, or when the iterator is dropped.
+    /// Pairs that weren't yielded are never removed, so stopping early leaves them in the table.
+    ///
+    /// Errors that occur while removing the last yielded pairs on drop are ignored;
+    /// exhaust the iterator to observe them.
+    pub fn drain_iter<'a, K, V>(
+        &'a mut self,
+        table_def: TableDefinition<'a, Bincode<K>, Bincode<V>>,
+    ) -> Result<Drain<'a, K, V>, CakeError>
+    where
+        K: DbKey,
+        V: DbValue,
+    {
+        let mut drain = Drain {
+            db: self,
+            table_def,
+            chunk: VecDeque::new(),
+            yielded: 0,
+            done: false,
+        };
+        drain.refill()?;
+
+        Ok(drain)
+    }
+
     /// Clears the contents of the given table, removing all key-value pairs.
     pub fn clear_table<K, V>(
         &self,
         table_def: TableDefinition<Bincode<K>, Bincode<V>>,
-    ) -> Result<(), Box<dyn std::error::Error>>
+    ) -> Result<(), CakeError>
     where
         K: DbKey,
         V: DbValue,
     {
+        let was_empty;
+
         let transaction = self.inner.begin_write()?;
         {
             let mut table = transaction.open_table(table_def)?;
+            was_empty = table.is_empty()?;
             table.retain(|_, _| false)?;
         }
+        if !was_empty {
+            self.record_write(&transaction, table_def.name())?;
+        }
         transaction.commit()?;
 
         Ok(())
@@ -90,7 +429,7 @@ impl CakeDb {
     pub fn delete_table<K, V>(
         &self,
         table_def: TableDefinition<Bincode<K>, Bincode<V>>,
-    ) -> Result<bool, Box<dyn std::error::Error>>
+    ) -> Result<bool, CakeError>
     where
         K: DbKey,
         V: DbValue,
@@ -101,8 +440,207 @@ impl CakeDb {
         {
             existed = transaction.delete_table(table_def)?;
         }
+        self.record_write(&transaction, table_def.name())?;
         transaction.commit()?;
 
         Ok(existed)
     }
+
+    /// Like [`clear_table`](Self::clear_table), but first takes a savepoint if
+    /// [`set_auto_savepoint_on_destructive`](Self::set_auto_savepoint_on_destructive) is enabled.
+    ///
+    /// Returns the key of that savepoint, if one was taken.
+    pub fn clear_table_safe<K, V>(
+        &mut self,
+        table_def: TableDefinition<Bincode<K>, Bincode<V>>,
+    ) -> Result<Option<usize>, CakeError>
+    where
+        K: DbKey,
+        V: DbValue,
+    {
+        let save_key = self.auto_savepoint()?;
+        self.clear_table(table_def)?;
+
+        Ok(save_key)
+    }
+
+    /// Like [`delete_table`](Self::delete_table), but first takes a savepoint if
+    /// [`set_auto_savepoint_on_destructive`](Self::set_auto_savepoint_on_destructive) is enabled.
+    ///
+    /// Returns whether the table existed, and the key of that savepoint, if one was taken.
+    pub fn delete_table_safe<K, V>(
+        &mut self,
+        table_def: TableDefinition<Bincode<K>, Bincode<V>>,
+    ) -> Result<(bool, Option<usize>), CakeError>
+    where
+        K: DbKey,
+        V: DbValue,
+    {
+        let save_key = self.auto_savepoint()?;
+        let existed = self.delete_table(table_def)?;
+
+        Ok((existed, save_key))
+    }
+
+    /// Like [`drain_iter`](Self::drain_iter), but first takes a savepoint if
+    /// [`set_auto_savepoint_on_destructive`](Self::set_auto_savepoint_on_destructive) is enabled.
+    ///
+    /// Returns the drain, and the key of that savepoint, if one was taken.
+    #[allow(clippy::type_complexity)]
+    pub fn drain_iter_safe<'a, K, V>(
+        &'a mut self,
+        table_def: TableDefinition<'a, Bincode<K>, Bincode<V>>,
+    ) -> Result<(Drain<'a, K, V>, Option<usize>), CakeError>
+    where
+        K: DbKey,
+        V: DbValue,
+    {
+        let save_key = self.auto_savepoint()?;
+        let drain = self.drain_iter(table_def)?;
+
+        Ok((drain, save_key))
+    }
+}
+
+type PruneFn = dyn Fn(&WriteTransaction) -> Result<u64, CakeError> + Send + Sync;
+
+/// Byte budget registered with [`CakeDb::set_table_budget`].
+pub(crate) struct TableBudget {
+    pub(crate) check_interval: NonZeroU64,
+    pub(crate) prune: Box<PruneFn>,
+}
+
+/// Removes pairs from the table named `name` in ascending key order until it stores at most
+/// `max_bytes` of keys and values. Returns how many pairs were removed.
+fn prune_raw_table<K, V>(
+    transaction: &WriteTransaction,
+    name: &str,
+    max_bytes: u64,
+) -> Result<u64, CakeError>
+where
+    K: DbKey + 'static,
+    V: DbValue + 'static,
+{
+    let raw_def: TableDefinition<RawBincode<K>, RawBincode<V>> = TableDefinition::new(name);
+    let mut table = transaction.open_table(raw_def)?;
+
+    let mut removed = 0;
+    let mut stored = table.stats()?.stored_bytes();
+    while stored > max_bytes {
+        let Some((kg, vg)) = table.pop_first()? else {
+            break;
+        };
+        let popped = (kg.value().len() + vg.value().len()) as u64;
+        drop((kg, vg));
+        removed += 1;
+
+        // Track the size from what was popped, but confirm with the table's own stats before
+        // stopping, so a drifting estimate can neither stop early nor run past zero.
+        stored = stored.saturating_sub(popped);
+        if stored <= max_bytes {
+            stored = table.stats()?.stored_bytes();
+        }
+    }
+
+    Ok(removed)
+}
+
+/// How many pairs [`Drain`] reads from the table at a time.
+pub const DRAIN_CHUNK_SIZE: usize = 1024;
+
+/// Iterator returned by [`CakeDb::drain_iter`].
+pub struct Drain<'a, K, V>
+where
+    K: DbKey + 'static,
+    V: DbValue + 'static,
+{
+    db: &'a CakeDb,
+    table_def: TableDefinition<'a, Bincode<K>, Bincode<V>>,
+    chunk: VecDeque<(K, V)>,
+    /// Number of pairs yielded since the last removal.
+    yielded: usize,
+    done: bool,
+}
+
+impl<K, V> Drain<'_, K, V>
+where
+    K: DbKey + 'static,
+    V: DbValue + 'static,
+{
+    /// Removes the pairs yielded so far.
+    ///
+    /// Nothing else can write to the database while the drain borrows it,
+    /// so the yielded pairs are always the first ones in the table.
+    fn remove_yielded(&mut self) -> Result<(), CakeError> {
+        if self.yielded == 0 {
+            return Ok(());
+        }
+
+        let transaction = self.db.inner.begin_write()?;
+        {
+            let mut table = transaction.open_table(self.table_def)?;
+            for _ in 0..self.yielded {
+                table.pop_first()?;
+            }
+        }
+        self.db.record_write(&transaction, self.table_def.name())?;
+        transaction.commit()?;
+        self.yielded = 0;
+
+        Ok(())
+    }
+
+    /// Removes the yielded pairs and reads the next chunk.
+    fn refill(&mut self) -> Result<(), CakeError> {
+        self.remove_yielded()?;
+
+        self.chunk = self
+            .db
+            .read_table(self.table_def)?
+            .range::<K>(..)?
+            .take(DRAIN_CHUNK_SIZE)
+            .map(|pair| pair.map(|(kg, vg)| (kg.value(), vg.value())))
+            .collect::<Result<_, _>>()?;
+
+        Ok(())
+    }
+}
+
+impl<K, V> Iterator for Drain<'_, K, V>
+where
+    K: DbKey + 'static,
+    V: DbValue + 'static,
+{
+    type Item = Result<(K, V), CakeError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+
+        if self.chunk.is_empty() {
+            if let Err(e) = self.refill() {
+                self.done = true;
+                return Some(Err(e));
+            }
+        }
+
+        let Some(pair) = self.chunk.pop_front() else {
+            self.done = true;
+            return None;
+        };
+        self.yielded += 1;
+
+        Some(Ok(pair))
+    }
+}
+
+impl<K, V> Drop for Drain<'_, K, V>
+where
+    K: DbKey + 'static,
+    V: DbValue + 'static,
+{
+    fn drop(&mut self) {
+        let _ = self.remove_yielded();
+    }
 }
@@ -0,0 +1,436 @@
+use std::{
+    cell::RefCell,
+    cmp::Ordering,
+    fmt::{self, Debug, Formatter},
+    path::Path,
+};
+
+use redb::{
+    Key, MultimapTableDefinition, MultimapTableHandle, ReadTransaction, ReadableMultimapTable,
+    ReadableTable, TableDefinition, TableError, TableHandle, TypeName, Value, WriteTransaction,
+};
+use tempfile::NamedTempFile;
+
+use crate::error::CakeError;
+
+/// Column of a table whose type is only known at runtime.
+struct Column {
+    type_name: TypeName,
+    fixed_width: Option<usize>,
+    /// The entry being appended, which sorts after every entry already in the table.
+    appending: Vec<u8>,
+}
+
+impl Column {
+    fn new() -> Self {
+        Self {
+            type_name: TypeName::new(""),
+            fixed_width: None,
+            appending: Vec::new(),
+        }
+    }
+}
+
+/// State behind [`Dynamic`] for the table currently being accessed on this thread.
+struct Columns {
+    columns: [Column; 2],
+    /// Set when `redb` compared two entries whose order isn't known.
+    unordered: bool,
+}
+
+impl Columns {
+    fn new() -> Self {
+        Self {
+            columns: [Column::new(), Column::new()],
+            unordered: false,
+        }
+    }
+}
+
+const KEY: usize = 0;
+const VALUE: usize = 1;
+
+thread_local! {
+    static COLUMNS: RefCell<Columns> = RefCell::new(Columns::new());
+}
+
+/// Stand-in for the key or value type of a table, taking whatever type the stored table declares.
+///
+/// The real order of the entries is unknown, so entries can only be inserted in the order they
+/// were read from a table of the same type: each one is appended after the rest. Comparing any
+/// other pair of entries flags the table as unordered, which [`append`] reports as an error.
+/// Accesses run on a single thread, so the declared types live in [`COLUMNS`].
+struct Dynamic<const COLUMN: usize>;
+
+impl<const COLUMN: usize> Debug for Dynamic<COLUMN> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "Dynamic<{COLUMN}>")
+    }
+}
+
+impl<const COLUMN: usize> Value for Dynamic<COLUMN> {
+    type SelfType<'a>
+        = &'a [u8]
+    where
+        Self: 'a;
+
+    type AsBytes<'a>
+        = &'a [u8]
+    where
+        Self: 'a;
+
+    fn fixed_width() -> Option<usize> {
+        COLUMNS.with_borrow(|state| state.columns[COLUMN].fixed_width)
+    }
+
+    fn from_bytes<'a>(data: &'a [u8]) -> Self::SelfType<'a>
+    where
+        Self: 'a,
+    {
+        data
+    }
+
+    fn as_bytes<'a, 'b: 'a>(value: &'a Self::SelfType<'b>) -> Self::AsBytes<'a>
+    where
+        Self: 'a + 'b,
+    {
+        value
+    }
+
+    fn type_name() -> TypeName {
+        COLUMNS.with_borrow(|state| state.columns[COLUMN].type_name.clone())
+    }
+}
+
+impl<const COLUMN: usize> Key for Dynamic<COLUMN> {
+    fn compare(data1: &[u8], data2: &[u8]) -> Ordering {
+        if data1 == data2 {
+            return Ordering::Equal;
+        }
+
+        COLUMNS.with_borrow_mut(|state| {
+            let appending = state.columns[COLUMN].appending.as_slice();
+            if data1 == appending {
+                Ordering::Greater
+            } else if data2 == appending {
+                Ordering::Less
+            } else {
+                state.unordered = true;
+                Ordering::Equal
+            }
+        })
+    }
+}
+
+/// Opens a table through `open` with [`Dynamic`] columns, first adopting the types it was
+/// created with.
+///
+/// `redb` reports the stored type names and fixed widths as errors on mismatch, so keep
+/// retrying with what they reveal. Widths come without saying whether they belong to the key
+/// or the value, so every combination of the widths seen so far is tried.
+fn open_dynamic<T>(mut open: impl FnMut() -> Result<T, TableError>) -> Result<T, CakeError> {
+    COLUMNS.set(Columns::new());
+
+    let mut widths = vec![None];
+    'retry: loop {
+        let mut last_error = None;
+        for i in 0..widths.len() * widths.len() {
+            COLUMNS.with_borrow_mut(|state| {
+                state.columns[KEY].fixed_width = widths[i / widths.len()];
+                state.columns[VALUE].fixed_width = widths[i % widths.len()];
+            });
+
+            match open() {
+                Ok(table) => return Ok(table),
+                Err(TableError::TableTypeMismatch { key, value, .. })
+                    if key != Dynamic::<KEY>::type_name()
+                        || value != Dynamic::<VALUE>::type_name() =>
+                {
+                    COLUMNS.with_borrow_mut(|state| {
+                        state.columns[KEY].type_name = key;
+                        state.columns[VALUE].type_name = value;
+                    });
+                    continue 'retry;
+                }
+                Err(TableError::TypeDefinitionChanged { width, .. })
+                    if !widths.contains(&width) =>
+                {
+                    widths.push(width);
+                    continue 'retry;
+                }
+                Err(e @ TableError::TypeDefinitionChanged { .. }) => last_error = Some(e),
+                Err(e) => return Err(e.into()),
+            }
+        }
+
+        if let Some(e) = last_error {
+            return Err(e.into());
+        }
+    }
+}
+
+/// Runs `insert` with `key` and `value` marked as the entries being appended.
+///
+/// Returns an error if inserting needed to order entries other than the appended ones,
+/// which means they weren't passed in the order they're stored in.
+fn append(
+    key: &[u8],
+    value: &[u8],
+    insert: impl FnOnce() -> Result<(), CakeError>,
+) -> Result<(), CakeError> {
+    COLUMNS.with_borrow_mut(|state| {
+        for (column, data) in state.columns.iter_mut().zip([key, value]) {
+            column.appending.clear();
+            column.appending.extend_from_slice(data);
+        }
+    });
+    insert()?;
+
+    if COLUMNS.with_borrow(|state| state.unordered) {
+        return Err(CakeError::InvalidArgument(
+            "entries weren't appended in their stored order".to_string(),
+        ));
+    }
+
+    Ok(())
+}
+
+/// Stored key and value types of a table, as declared when it was created.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct TableTypes {
+    pub(crate) key: TypeName,
+    pub(crate) key_width: Option<usize>,
+    pub(crate) value: TypeName,
+    pub(crate) value_width: Option<usize>,
+}
+
+impl TableTypes {
+    /// Returns the types [`Dynamic`] currently stands in for.
+    fn current() -> Self {
+        COLUMNS.with_borrow(|state| Self {
+            key: state.columns[KEY].type_name.clone(),
+            key_width: state.columns[KEY].fixed_width,
+            value: state.columns[VALUE].type_name.clone(),
+            value_width: state.columns[VALUE].fixed_width,
+        })
+    }
+
+    /// Makes [`Dynamic`] stand in for these types.
+    fn adopt(&self) {
+        COLUMNS.set(Columns::new());
+        COLUMNS.with_borrow_mut(|state| {
+            state.columns[KEY].type_name = self.key.clone();
+            state.columns[KEY].fixed_width = self.key_width;
+            state.columns[VALUE].type_name = self.value.clone();
+            state.columns[VALUE].fixed_width = self.value_width;
+        });
+    }
+}
+
+/// Returns the [`TypeName`] of a stored type from its name alone, e.g. as written to a snapshot.
+///
+/// `redb` tells its built-in types apart from user-defined ones with the same name, so the common
+/// built-in types are matched by name. Composite built-in types like tuples or `Option<T>` come
+/// back as user-defined, and a table created with them can then only be read by name.
+pub(crate) fn stored_type_name(name: &str) -> TypeName {
+    macro_rules! built_in {
+        ($($ty:ty),* $(,)?) => {
+            $(
+                if name == <$ty as Value>::type_name().name() {
+                    return <$ty as Value>::type_name();
+                }
+            )*
+        };
+    }
+
+    built_in!(
+        (),
+        bool,
+        char,
+        &str,
+        String,
+        &[u8],
+        u8,
+        u16,
+        u32,
+        u64,
+        u128,
+        i8,
+        i16,
+        i32,
+        i64,
+        i128,
+        f32,
+        f64,
+    );
+
+    TypeName::new(name)
+}
+
+/// Calls `f` with the stored bytes of every key-value pair of the table `name` in `read`, in key order.
+///
+/// The table's types don't need to be known; they're discovered and returned. Returns `None`
+/// without calling `f` if there's no table named `name`. `f` must not access other tables by name.
+pub(crate) fn visit_raw_table(
+    read: &ReadTransaction,
+    name: &str,
+    mut f: impl FnMut(&[u8], &[u8]) -> Result<(), CakeError>,
+) -> Result<Option<TableTypes>, CakeError> {
+    let def = TableDefinition::<Dynamic<KEY>, Dynamic<VALUE>>::new(name);
+    let result = match open_dynamic(|| read.open_table(def)) {
+        Ok(table) => table.iter().map_err(CakeError::from).and_then(|iter| {
+            for pair in iter {
+                let (kg, vg) = pair?;
+                f(kg.value(), vg.value())?;
+            }
+            Ok(Some(TableTypes::current()))
+        }),
+        Err(CakeError::Table(TableError::TableDoesNotExist(_))) => Ok(None),
+        Err(e) => Err(e),
+    };
+    COLUMNS.set(Columns::new());
+
+    result
+}
+
+/// Calls `f` with the stored bytes of every key-value pair of the multimap table `name` in `read`,
+/// in key order, then in value order within each key.
+///
+/// Works like [`visit_raw_table`], but for multimap tables.
+pub(crate) fn visit_raw_multimap_table(
+    read: &ReadTransaction,
+    name: &str,
+    mut f: impl FnMut(&[u8], &[u8]) -> Result<(), CakeError>,
+) -> Result<Option<TableTypes>, CakeError> {
+    let def = MultimapTableDefinition::<Dynamic<KEY>, Dynamic<VALUE>>::new(name);
+    let result = match open_dynamic(|| read.open_multimap_table(def)) {
+        Ok(table) => table.iter().map_err(CakeError::from).and_then(|iter| {
+            for entry in iter {
+                let (kg, values) = entry?;
+                for value in values {
+                    f(kg.value(), value?.value())?;
+                }
+            }
+            Ok(Some(TableTypes::current()))
+        }),
+        Err(CakeError::Table(TableError::TableDoesNotExist(_))) => Ok(None),
+        Err(e) => Err(e),
+    };
+    COLUMNS.set(Columns::new());
+
+    result
+}
+
+/// Replaces the table `name` in `write` with a table of the given `types` holding `pairs`.
+///
+/// `pairs` must come in the order a table of these types stores them, e.g. as read by
+/// [`visit_raw_table`]. Misordered pairs are only caught if `redb` happens to compare two
+/// entries other than the one being appended, so they can otherwise leave the table out of
+/// order. On error, `write` must be aborted.
+pub(crate) fn replace_raw_table<'a>(
+    write: &WriteTransaction,
+    name: &str,
+    types: &TableTypes,
+    pairs: impl IntoIterator<Item = (&'a [u8], &'a [u8])>,
+) -> Result<(), CakeError> {
+    let def = TableDefinition::<Dynamic<KEY>, Dynamic<VALUE>>::new(name);
+    types.adopt();
+    let result = write
+        .delete_table(def)
+        .map_err(CakeError::from)
+        .and_then(|_| {
+            let mut table = write.open_table(def)?;
+            for (key, value) in pairs {
+                append(key, value, || {
+                    table.insert(key, value)?;
+                    Ok(())
+                })?;
+            }
+            Ok(())
+        });
+    COLUMNS.set(Columns::new());
+
+    result
+}
+
+/// Copies every table and multimap table of `read` into `write`, keeping their stored bytes and types.
+///
+/// The tables must not exist in `write` yet.
+fn copy_tables(read: &ReadTransaction, write: &WriteTransaction) -> Result<(), CakeError> {
+    let result = copy_all(read, write);
+    COLUMNS.set(Columns::new());
+
+    result
+}
+
+fn copy_all(read: &ReadTransaction, write: &WriteTransaction) -> Result<(), CakeError> {
+    for handle in read.list_tables()? {
+        let def = TableDefinition::<Dynamic<KEY>, Dynamic<VALUE>>::new(handle.name());
+        let source = open_dynamic(|| read.open_table(def))?;
+        let mut target = write.open_table(def)?;
+
+        for pair in source.iter()? {
+            let (kg, vg) = pair?;
+            append(kg.value(), vg.value(), || {
+                target.insert(kg.value(), vg.value())?;
+                Ok(())
+            })?;
+        }
+    }
+
+    for handle in read.list_multimap_tables()? {
+        let def = MultimapTableDefinition::<Dynamic<KEY>, Dynamic<VALUE>>::new(handle.name());
+        let source = open_dynamic(|| read.open_multimap_table(def))?;
+        let mut target = write.open_multimap_table(def)?;
+
+        for entry in source.iter()? {
+            let (kg, values) = entry?;
+            for value in values {
+                let vg = value?;
+                append(kg.value(), vg.value(), || {
+                    target.insert(kg.value(), vg.value())?;
+                    Ok(())
+                })?;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Writes a copy of every table of `read` to a fresh database file at `path`.
+///
+/// The copy is built in a temporary file next to `path` and only moved there once it's
+/// committed, so a failure never leaves a partial database behind. If `overwrite` is `false`,
+/// returns an error instead of replacing an existing file.
+pub(crate) fn write_copy(
+    read: &ReadTransaction,
+    path: &Path,
+    overwrite: bool,
+) -> Result<(), CakeError> {
+    let temp = staging_file(path)?;
+
+    let copy = redb::Database::builder().create_file(temp.reopen()?)?;
+    let write = copy.begin_write()?;
+    copy_tables(read, &write)?;
+    write.commit()?;
+    drop(copy);
+
+    if overwrite {
+        temp.persist(path).map_err(|e| e.error)?;
+    } else {
+        temp.persist_noclobber(path).map_err(|e| e.error)?;
+    }
+
+    Ok(())
+}
+
+/// Creates a temporary file in the directory of `path`, so it can later be renamed over `path`.
+pub(crate) fn staging_file(path: &Path) -> Result<NamedTempFile, CakeError> {
+    let dir = match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+
+    Ok(NamedTempFile::new_in(dir)?)
+}
@@ -1,67 +1,215 @@
-use anyhow::anyhow;
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    hash::{DefaultHasher, Hash, Hasher},
+};
+
 use redb::{
-    MultimapTableDefinition, ReadOnlyMultimapTable, ReadOnlyTable, ReadableDatabase,
-    TableDefinition, TableError,
+    Key, MultimapTableDefinition, ReadOnlyMultimapTable, ReadOnlyTable, ReadTransaction,
+    ReadableDatabase, ReadableTable, TableDefinition, TableError, TableHandle, Value,
+    WriteTransaction,
 };
 
-use crate::{bincode_wrapper::Bincode, CakeDb};
+use crate::{
+    bincode_wrapper::{Bincode, RawBincode},
+    error::CakeError,
+    CakeDb,
+};
 
 use super::traits::{DbKey, DbValue};
 
+/// Metadata table holding the revision of each regular table, keyed by table name.
+pub(crate) const REVISIONS_TABLE: TableDefinition<&str, u64> =
+    TableDefinition::new("__cakedb_revisions");
+
 impl CakeDb {
     /// Opens the given table as read-only and returns it.
     pub(super) fn read_table<K, V>(
         &self,
         table_def: TableDefinition<Bincode<K>, Bincode<V>>,
-    ) -> Result<ReadOnlyTable<Bincode<K>, Bincode<V>>, Box<dyn std::error::Error>>
+    ) -> Result<ReadOnlyTable<Bincode<K>, Bincode<V>>, CakeError>
     where
         K: DbKey,
         V: DbValue,
     {
-        let read = self
-            .inner
-            .begin_read()
-            .map_err(|e| anyhow!("failed to begin read for '{table_def}': {e}"))?;
+        let read = self.inner.begin_read()?;
         match read.open_table(table_def) {
-            Err(TableError::TableDoesNotExist(outer_err)) => {
+            Err(TableError::TableDoesNotExist(_)) => {
                 // `open_table` from a `ReadTransaction` doesn't create the table if it doesn't exist,
                 // so create it with a `WriteTransaction` here.
-                let write = self.inner.begin_write().map_err(|e| anyhow!("Failed to begin write transaction to create a table: {e} (Tried creating a table because of this error: {outer_err})"))?;
-                write.open_table(table_def).map_err(|e| anyhow!("Failed to open table: {e} (Tried creating a table because of this error: {outer_err})"))?;
-                write.commit().map_err(|e| anyhow!("Failed to commit write transaction creating table: {e} (Tried creating a table because of this error: {outer_err})"))?;
+                let write = self.inner.begin_write()?;
+                write.open_table(table_def)?;
+                write.commit()?;
 
-                let read = self
-                    .inner
-                    .begin_read()
-                    .map_err(|e| anyhow!("failed to begin read for '{table_def}': {e}"))?;
-
-                let table = read.open_table(table_def).map_err(|e| {
-                    anyhow!(
-                        "failed to open table for '{table_def}': {e} (initial error: {outer_err}"
-                    )
-                })?;
+                let table = self.inner.begin_read()?.open_table(table_def)?;
 
                 Ok(table)
             }
-            Err(e) => Err(anyhow!("Failed to open table for '{table_def}': {e}").into()),
+            Err(e) => Err(e.into()),
             Ok(table) => Ok(table),
         }
     }
 
+    /// Opens the given table as read-only, returning `None` instead of creating it if it doesn't exist.
+    #[allow(clippy::type_complexity)]
+    pub(super) fn try_read_table<K, V>(
+        &self,
+        table_def: TableDefinition<Bincode<K>, Bincode<V>>,
+    ) -> Result<Option<ReadOnlyTable<Bincode<K>, Bincode<V>>>, CakeError>
+    where
+        K: DbKey,
+        V: DbValue,
+    {
+        let read = self.inner.begin_read()?;
+        open_existing_table(&read, table_def)
+    }
+
+    /// Opens the given table as read-only, without decoding its keys and values.
+    ///
+    /// Returns `None` if the table doesn't exist.
+    #[allow(clippy::type_complexity)]
+    pub(crate) fn open_raw_table<K, V>(
+        &self,
+        table_def: TableDefinition<Bincode<K>, Bincode<V>>,
+    ) -> Result<Option<ReadOnlyTable<RawBincode<K>, RawBincode<V>>>, CakeError>
+    where
+        K: DbKey,
+        V: DbValue,
+    {
+        let read = self.inner.begin_read()?;
+        open_existing_table(&read, TableDefinition::new(table_def.name()))
+    }
+
     /// Opens the given multimap table as read-only and returns it.
     pub(super) fn read_multimap_table<K, V>(
         &self,
         table_def: MultimapTableDefinition<Bincode<K>, Bincode<V>>,
-    ) -> Result<ReadOnlyMultimapTable<Bincode<K>, Bincode<V>>, Box<dyn std::error::Error>>
+    ) -> Result<ReadOnlyMultimapTable<Bincode<K>, Bincode<V>>, CakeError>
     where
         K: DbKey,
         V: DbValue + Ord,
     {
-        Ok(self
-            .inner
-            .begin_read()
-            .map_err(|e| anyhow!("failed to begin read for '{table_def}': {e}"))?
-            .open_multimap_table(table_def)
-            .map_err(|e| anyhow!("failed to open table for '{table_def}': {e}"))?)
+        Ok(self.inner.begin_read()?.open_multimap_table(table_def)?)
     }
 }
+
+/// Encodes a key-value pair ahead of a write, so encoding failures surface as errors
+/// before the transaction is touched instead of panicking inside it.
+pub(crate) fn encode_pair<K, V>(key: &K, value: &V) -> Result<(Vec<u8>, Vec<u8>), CakeError>
+where
+    K: DbKey,
+    V: DbValue,
+{
+    Ok((
+        Bincode::<K>::try_as_bytes(key)?,
+        Bincode::<V>::try_as_bytes(value)?,
+    ))
+}
+
+/// Opens the given table from `read`, returning `None` if it doesn't exist.
+pub(crate) fn open_existing_table<K, V>(
+    read: &ReadTransaction,
+    table_def: TableDefinition<K, V>,
+) -> Result<Option<ReadOnlyTable<K, V>>, CakeError>
+where
+    K: Key + 'static,
+    V: Value + 'static,
+{
+    match read.open_table(table_def) {
+        Ok(table) => Ok(Some(table)),
+        Err(TableError::TableDoesNotExist(_)) => Ok(None),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Increments the revision of the table named `table` as part of `transaction`, returning the new revision.
+fn bump_revision(transaction: &WriteTransaction, table: &str) -> Result<u64, CakeError> {
+    let mut revisions = transaction.open_table(REVISIONS_TABLE)?;
+    let revision = revisions.get(table)?.map_or(0, |g| g.value()) + 1;
+    revisions.insert(table, revision)?;
+
+    Ok(revision)
+}
+
+/// Returns the revision of every table in `read` that has one, keyed by table name.
+pub(crate) fn read_revisions(read: &ReadTransaction) -> Result<BTreeMap<String, u64>, CakeError> {
+    let mut revisions = BTreeMap::new();
+    if let Some(table) = open_existing_table(read, REVISIONS_TABLE)? {
+        for pair in table.iter()? {
+            let (name, revision) = pair?;
+            revisions.insert(name.value().to_string(), revision.value());
+        }
+    }
+
+    Ok(revisions)
+}
+
+/// Moves every table past all revisions in `before` as part of `transaction`.
+///
+/// Restoring a savepoint or checkpoint rolls the revisions back along with the data. Pass the
+/// revisions read just before the restore, so that every table, whether it exists before or
+/// after, gets a revision newer than any handed out so far.
+pub(crate) fn advance_revisions(
+    transaction: &WriteTransaction,
+    before: BTreeMap<String, u64>,
+) -> Result<(), CakeError> {
+    let mut revisions = transaction.open_table(REVISIONS_TABLE)?;
+
+    let mut latest = before.values().copied().max().unwrap_or(0);
+    let mut names: BTreeSet<String> = before.into_keys().collect();
+    for pair in revisions.iter()? {
+        let (name, revision) = pair?;
+        names.insert(name.value().to_string());
+        latest = latest.max(revision.value());
+    }
+
+    for name in &names {
+        revisions.insert(name.as_str(), latest + 1)?;
+    }
+
+    Ok(())
+}
+
+impl CakeDb {
+    /// Records a write to `table` within `transaction`: bumps its revision, then enforces its
+    /// [budget](CakeDb::set_table_budget) if the new revision is due for a check.
+    pub(crate) fn record_write(
+        &self,
+        transaction: &WriteTransaction,
+        table: &str,
+    ) -> Result<(), CakeError> {
+        let revision = bump_revision(transaction, table)?;
+        if let Some(budget) = self.table_budgets.get(table) {
+            if revision % budget.check_interval.get() == 0 {
+                (budget.prune)(transaction)?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Hashes the stored bytes of every key-value pair of the given table in `read`, in key order.
+///
+/// A missing table hashes the same as an empty one.
+pub(crate) fn checksum_table<K, V>(
+    read: &ReadTransaction,
+    table_def: TableDefinition<Bincode<K>, Bincode<V>>,
+) -> Result<u64, CakeError>
+where
+    K: DbKey,
+    V: DbValue,
+{
+    let raw_def: TableDefinition<RawBincode<K>, RawBincode<V>> =
+        TableDefinition::new(table_def.name());
+
+    let mut hasher = DefaultHasher::new();
+    if let Some(table) = open_existing_table(read, raw_def)? {
+        for pair in table.iter()? {
+            let (kg, vg) = pair?;
+            kg.value().hash(&mut hasher);
+            vg.value().hash(&mut hasher);
+        }
+    }
+
+    Ok(hasher.finish())
+}
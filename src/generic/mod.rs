@@ -1,7 +1,10 @@
 pub mod batch_writes;
+pub mod copy;
 pub mod internal;
 pub mod multimap_reads;
 pub mod multimap_writes;
+pub mod read_transaction;
 pub mod reads;
 pub mod traits;
+pub mod write_transaction;
 pub mod writes;
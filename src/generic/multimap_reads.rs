@@ -1,18 +1,45 @@
-use std::collections::{BTreeMap, BTreeSet};
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    ops::Bound,
+};
 
-use redb::{MultimapTableDefinition, ReadableMultimapTable};
+use redb::{
+    MultimapTableDefinition, MultimapTableHandle, ReadableDatabase, ReadableMultimapTable,
+    ReadableTableMetadata, TableError,
+};
 
-use crate::{bincode_wrapper::Bincode, CakeDb};
+use crate::{bincode_wrapper::Bincode, error::CakeError, CakeDb};
 
 use super::traits::{DbKey, DbValue};
 
+/// Storage statistics for a multimap table.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MultimapTableStats {
+    /// Number of distinct keys.
+    pub key_count: u64,
+    /// Total number of values across all keys.
+    pub value_count: u64,
+    /// Maximum traversal distance to reach the deepest key-value pair.
+    pub tree_height: u32,
+    /// Number of leaf pages that store user data.
+    pub leaf_pages: u64,
+    /// Number of branch pages in the tree.
+    pub branch_pages: u64,
+    /// Number of bytes consumed by keys and values.
+    pub stored_bytes: u64,
+    /// Number of bytes consumed by keys in internal branch pages, plus other metadata.
+    pub metadata_bytes: u64,
+    /// Number of bytes consumed by fragmentation, both in data pages and internal metadata tables.
+    pub fragmented_bytes: u64,
+}
+
 impl CakeDb {
     /// Returns all values mapped to the given key.
     pub fn multimap_get<K, V>(
         &self,
         table_def: MultimapTableDefinition<Bincode<K>, Bincode<V>>,
         key: &K,
-    ) -> Result<BTreeSet<V>, Box<dyn std::error::Error>>
+    ) -> Result<BTreeSet<V>, CakeError>
     where
         K: DbKey,
         V: DbValue + Ord,
@@ -29,7 +56,7 @@ impl CakeDb {
     pub fn multimap_table<K, V>(
         &self,
         table_def: MultimapTableDefinition<Bincode<K>, Bincode<V>>,
-    ) -> Result<BTreeMap<K, BTreeSet<V>>, Box<dyn std::error::Error>>
+    ) -> Result<BTreeMap<K, BTreeSet<V>>, CakeError>
     where
         K: DbKey,
         V: DbValue + Ord,
@@ -46,4 +73,137 @@ impl CakeDb {
             })
             .collect())
     }
+
+    /// Returns `true` if the given multimap table has no values, or doesn't exist yet.
+    pub fn multimap_is_empty<K, V>(
+        &self,
+        table_def: MultimapTableDefinition<Bincode<K>, Bincode<V>>,
+    ) -> Result<bool, CakeError>
+    where
+        K: DbKey,
+        V: DbValue + Ord,
+    {
+        match self.inner.begin_read()?.open_multimap_table(table_def) {
+            Ok(table) => Ok(table.is_empty()?),
+            Err(TableError::TableDoesNotExist(_)) => Ok(true),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Returns the first key, in ascending order, that has at least one value matching the predicate.
+    ///
+    /// Keys are visited lazily, and the values of each key stop being checked at the first match.
+    pub fn multimap_find_key<K, V>(
+        &self,
+        table_def: MultimapTableDefinition<Bincode<K>, Bincode<V>>,
+        predicate: impl Fn(&K, &V) -> bool,
+    ) -> Result<Option<K>, CakeError>
+    where
+        K: DbKey,
+        V: DbValue + Ord,
+    {
+        for entry in self.read_multimap_table(table_def)?.iter()? {
+            let (key_guard, values) = entry?;
+            let key = key_guard.value();
+            for value in values {
+                if predicate(&key, &value?.value()) {
+                    return Ok(Some(key));
+                }
+            }
+        }
+
+        Ok(None)
+    }
+
+    /// Returns up to `limit` keys with their values, starting strictly after the key `after`.
+    ///
+    /// Pass `None` to start from the first key, then the last returned key to get the next page.
+    #[allow(clippy::type_complexity)]
+    pub fn multimap_page<K, V>(
+        &self,
+        table_def: MultimapTableDefinition<Bincode<K>, Bincode<V>>,
+        after: Option<&K>,
+        limit: u64,
+    ) -> Result<Vec<(K, BTreeSet<V>)>, CakeError>
+    where
+        K: DbKey,
+        V: DbValue + Ord,
+    {
+        let start = after.map_or(Bound::Unbounded, Bound::Excluded);
+
+        Ok(self
+            .read_multimap_table(table_def)?
+            .range::<&K>((start, Bound::Unbounded))?
+            .flatten()
+            .take(limit as usize)
+            .map(|(key_ag, value_ag)| {
+                (
+                    key_ag.value(),
+                    value_ag.flatten().map(|v| v.value()).collect(),
+                )
+            })
+            .collect())
+    }
+
+    /// Returns up to `limit` values of the given key, in ascending order, starting strictly after the value `after`.
+    ///
+    /// Pass `None` to start from the first value, then the last returned value to get the next page.
+    pub fn multimap_get_page<K, V>(
+        &self,
+        table_def: MultimapTableDefinition<Bincode<K>, Bincode<V>>,
+        key: &K,
+        after: Option<&V>,
+        limit: usize,
+    ) -> Result<Vec<V>, CakeError>
+    where
+        K: DbKey,
+        V: DbValue + Ord,
+    {
+        let mut page = Vec::new();
+        for vg in self.read_multimap_table(table_def)?.get(key)? {
+            if page.len() >= limit {
+                break;
+            }
+            let value = vg?.value();
+            if after.is_none_or(|after| value > *after) {
+                page.push(value);
+            }
+        }
+
+        Ok(page)
+    }
+
+    /// Returns the names of all multimap tables in the database.
+    pub fn list_multimap_tables(&self) -> Result<Vec<String>, CakeError> {
+        Ok(self
+            .inner
+            .begin_read()?
+            .list_multimap_tables()?
+            .map(|handle| handle.name().to_string())
+            .collect())
+    }
+
+    /// Returns the key and value counts of the given multimap table, along with its storage metrics.
+    pub fn multimap_table_stats<K, V>(
+        &self,
+        table_def: MultimapTableDefinition<Bincode<K>, Bincode<V>>,
+    ) -> Result<MultimapTableStats, CakeError>
+    where
+        K: DbKey,
+        V: DbValue + Ord,
+    {
+        let table = self.read_multimap_table(table_def)?;
+        let stats = table.stats()?;
+
+        Ok(MultimapTableStats {
+            key_count: table.iter()?.count() as u64,
+            value_count: table.len()?,
+            tree_height: stats.tree_height(),
+            leaf_pages: stats.leaf_pages(),
+            branch_pages: stats.branch_pages(),
+            stored_bytes: stats.stored_bytes(),
+            metadata_bytes: stats.metadata_bytes(),
+            fragmented_bytes: stats.fragmented_bytes(),
+        })
+    }
 }
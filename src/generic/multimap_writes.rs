@@ -1,9 +1,20 @@
-use redb::{MultimapTableDefinition, ReadableMultimapTable};
+use std::collections::BTreeMap;
 
-use crate::{bincode_wrapper::Bincode, CakeDb};
+use redb::{MultimapTableDefinition, ReadableMultimapTable, WriteTransaction};
+
+use crate::{bincode_wrapper::Bincode, error::CakeError, CakeDb};
 
 use super::traits::{DbKey, DbValue};
 
+/// Outcome of [`CakeDb::multimap_extend`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MultimapExtendReport {
+    /// Whether the key had at least one value mapped beforehand.
+    pub key_existed: bool,
+    /// Number of values that weren't already mapped to the key.
+    pub added: usize,
+}
+
 impl CakeDb {
     /// Adds a given value to the mapping of the key.
     ///
@@ -13,7 +24,7 @@ impl CakeDb {
         table_def: MultimapTableDefinition<Bincode<K>, Bincode<V>>,
         key: &K,
         value: V,
-    ) -> Result<bool, Box<dyn std::error::Error>>
+    ) -> Result<bool, CakeError>
     where
         K: DbKey,
         V: DbValue + Ord,
@@ -38,9 +49,9 @@ impl CakeDb {
         table_def: MultimapTableDefinition<Bincode<K>, Bincode<V>>,
         key: &K,
         values: impl IntoIterator<Item = V>,
-    ) -> Result<bool, Box<dyn std::error::Error>>
+    ) -> Result<bool, CakeError>
     where
-        K: DbKey + Clone,
+        K: DbKey,
         V: DbValue + Ord,
     {
         let mut existed = false;
@@ -61,6 +72,41 @@ impl CakeDb {
         Ok(existed)
     }
 
+    /// Adds the given values to the mapping of the key.
+    ///
+    /// Unlike [`multimap_insert_values`](Self::multimap_insert_values), also reports how many
+    /// of the values were newly added.
+    pub fn multimap_extend<K, V>(
+        &mut self,
+        table_def: MultimapTableDefinition<Bincode<K>, Bincode<V>>,
+        key: &K,
+        values: impl IntoIterator<Item = V>,
+    ) -> Result<MultimapExtendReport, CakeError>
+    where
+        K: DbKey,
+        V: DbValue + Ord,
+    {
+        let mut report = MultimapExtendReport {
+            key_existed: false,
+            added: 0,
+        };
+
+        let transaction = self.inner.begin_write()?;
+        {
+            let mut table = transaction.open_multimap_table(table_def)?;
+            report.key_existed = !table.get(key)?.is_empty();
+
+            for v in values.into_iter() {
+                if !table.insert(key, v)? {
+                    report.added += 1;
+                }
+            }
+        }
+        transaction.commit()?;
+
+        Ok(report)
+    }
+
     /// Inserts each value of each key into the table.
     ///
     /// `data` can be any data structure that can be iterated in the same way as a `Vec<(K, Vec<V>)>` or a `BTreeMap<K, Vec<V>>`.
@@ -68,9 +114,9 @@ impl CakeDb {
         &mut self,
         table_def: MultimapTableDefinition<Bincode<K>, Bincode<V>>,
         data: impl IntoIterator<Item = (K, impl IntoIterator<Item = V>)>,
-    ) -> Result<(), Box<dyn std::error::Error>>
+    ) -> Result<(), CakeError>
     where
-        K: DbKey + Clone,
+        K: DbKey,
         V: DbValue + Ord,
     {
         let transaction = self.inner.begin_write()?;
@@ -97,9 +143,9 @@ impl CakeDb {
         table_def: MultimapTableDefinition<Bincode<K>, Bincode<V>>,
         key: &K,
         values: impl IntoIterator<Item = V>,
-    ) -> Result<bool, Box<dyn std::error::Error>>
+    ) -> Result<bool, CakeError>
     where
-        K: DbKey + Clone,
+        K: DbKey,
         V: DbValue + Ord,
     {
         let mut existed = false;
@@ -121,6 +167,38 @@ impl CakeDb {
         Ok(existed)
     }
 
+    /// Assigns `values` to the mappings of the key, overwriting any previous values.
+    ///
+    /// Like [`multimap_assign`](Self::multimap_assign), but returns the removed values in ascending order.
+    pub fn multimap_replace_all<K, V>(
+        &mut self,
+        table_def: MultimapTableDefinition<Bincode<K>, Bincode<V>>,
+        key: &K,
+        values: impl IntoIterator<Item = V>,
+    ) -> Result<Vec<V>, CakeError>
+    where
+        K: DbKey,
+        V: DbValue + Ord,
+    {
+        let old_values: Vec<V>;
+
+        let transaction = self.inner.begin_write()?;
+        {
+            let mut table = transaction.open_multimap_table(table_def)?;
+            old_values = table
+                .remove_all(key)?
+                .map(|v| v.map(|guard| guard.value()))
+                .collect::<Result<_, _>>()?;
+
+            for v in values.into_iter() {
+                table.insert(key, v)?;
+            }
+        }
+        transaction.commit()?;
+
+        Ok(old_values)
+    }
+
     /// Removes a given value from the mapping of the key.
     ///
     /// Returns `true` if the value was present.
@@ -129,7 +207,7 @@ impl CakeDb {
         table_def: MultimapTableDefinition<Bincode<K>, Bincode<V>>,
         key: &K,
         value: V,
-    ) -> Result<bool, Box<dyn std::error::Error>>
+    ) -> Result<bool, CakeError>
     where
         K: DbKey,
         V: DbValue + Ord,
@@ -153,7 +231,7 @@ impl CakeDb {
         &mut self,
         table_def: MultimapTableDefinition<Bincode<K>, Bincode<V>>,
         key: &K,
-    ) -> Result<Vec<V>, Box<dyn std::error::Error>>
+    ) -> Result<Vec<V>, CakeError>
     where
         K: DbKey,
         V: DbValue + Ord,
@@ -174,11 +252,204 @@ impl CakeDb {
         Ok(values)
     }
 
+    /// Removes all values from each of the given keys in a single transaction.
+    ///
+    /// Returns the removed values of each key in ascending order. Keys that had no values map to an empty `Vec`.
+    pub fn multimap_remove_all_many<K, V, I>(
+        &mut self,
+        table_def: MultimapTableDefinition<Bincode<K>, Bincode<V>>,
+        keys: I,
+    ) -> Result<BTreeMap<K, Vec<V>>, CakeError>
+    where
+        K: DbKey,
+        V: DbValue + Ord,
+        I: IntoIterator<Item = K>,
+    {
+        let mut removed: BTreeMap<K, Vec<V>> = BTreeMap::new();
+
+        let transaction = self.inner.begin_write()?;
+        {
+            let mut table = transaction.open_multimap_table(table_def)?;
+            for key in keys {
+                let values = table.remove_all(&key)?.flatten().map(|v| v.value());
+                removed.entry(key).or_default().extend(values);
+            }
+        }
+        transaction.commit()?;
+
+        Ok(removed)
+    }
+
+    /// Removes all values from a key in the table without decoding them.
+    ///
+    /// Returns `true` if the key had at least one value mapped.
+    pub fn multimap_clear_key<K, V>(
+        &mut self,
+        table_def: MultimapTableDefinition<Bincode<K>, Bincode<V>>,
+        key: &K,
+    ) -> Result<bool, CakeError>
+    where
+        K: DbKey,
+        V: DbValue + Ord,
+    {
+        let existed: bool;
+
+        let transaction = self.inner.begin_write()?;
+        {
+            let mut table = transaction.open_multimap_table(table_def)?;
+            existed = !table.remove_all(key)?.is_empty();
+        }
+        transaction.commit()?;
+
+        Ok(existed)
+    }
+
+    /// Applies `edit` to every value of each of the given keys in a single transaction.
+    ///
+    /// Values that become equal after editing collapse into one mapping. Keys that aren't given are left untouched.
+    ///
+    /// Returns the number of values edited.
+    pub fn multimap_batch_update<K, V, I>(
+        &mut self,
+        table_def: MultimapTableDefinition<Bincode<K>, Bincode<V>>,
+        keys: I,
+        edit: impl Fn(&K, &mut V),
+    ) -> Result<u64, CakeError>
+    where
+        K: DbKey,
+        V: DbValue + Ord,
+        I: IntoIterator<Item = K>,
+    {
+        let mut edited = 0;
+
+        let transaction = self.inner.begin_write()?;
+        {
+            let mut table = transaction.open_multimap_table(table_def)?;
+            for key in keys {
+                let values = table
+                    .remove_all(&key)?
+                    .map(|v| v.map(|v| v.value()))
+                    .collect::<Result<Vec<V>, _>>()?;
+
+                for mut value in values {
+                    edit(&key, &mut value);
+                    table.insert(&key, value)?;
+                    edited += 1;
+                }
+            }
+        }
+        transaction.commit()?;
+
+        Ok(edited)
+    }
+
+    /// Removes every key-value mapping for which `keep` returns `false`.
+    ///
+    /// Keys left without any values are simply empty afterwards.
+    ///
+    /// Returns the number of removed mappings.
+    pub fn multimap_retain<K, V>(
+        &mut self,
+        table_def: MultimapTableDefinition<Bincode<K>, Bincode<V>>,
+        keep: impl Fn(&K, &V) -> bool,
+    ) -> Result<u64, CakeError>
+    where
+        K: DbKey,
+        V: DbValue + Ord,
+    {
+        let mut removed = 0;
+
+        let transaction = self.inner.begin_write()?;
+        {
+            let mut table = transaction.open_multimap_table(table_def)?;
+
+            let mut discarded = Vec::new();
+            for entry in table.iter()? {
+                let (key_guard, values) = entry?;
+                let key = key_guard.value();
+
+                let mut key_discarded = Vec::new();
+                for value in values {
+                    let value = value?.value();
+                    if !keep(&key, &value) {
+                        key_discarded.push(value);
+                    }
+                }
+
+                if !key_discarded.is_empty() {
+                    discarded.push((key, key_discarded));
+                }
+            }
+
+            for (key, values) in discarded {
+                for value in values {
+                    if table.remove(&key, value)? {
+                        removed += 1;
+                    }
+                }
+            }
+        }
+        transaction.commit()?;
+
+        Ok(removed)
+    }
+
+    /// Copies every key-value mapping of `from` into `to`.
+    ///
+    /// Mappings already present in `to` are kept.
+    ///
+    /// Returns the number of copied mappings.
+    pub fn copy_multimap_table<K, V>(
+        &mut self,
+        from: MultimapTableDefinition<Bincode<K>, Bincode<V>>,
+        to: MultimapTableDefinition<Bincode<K>, Bincode<V>>,
+    ) -> Result<u64, CakeError>
+    where
+        K: DbKey,
+        V: DbValue + Ord,
+    {
+        let copied: u64;
+
+        let transaction = self.inner.begin_write()?;
+        {
+            copied = copy_multimap_mappings(&transaction, from, to)?;
+        }
+        transaction.commit()?;
+
+        Ok(copied)
+    }
+
+    /// Moves every key-value mapping of `from` into `to`, then deletes `from`.
+    ///
+    /// Mappings already present in `to` are kept.
+    ///
+    /// Returns the number of moved mappings.
+    pub fn rename_multimap_table<K, V>(
+        &mut self,
+        from: MultimapTableDefinition<Bincode<K>, Bincode<V>>,
+        to: MultimapTableDefinition<Bincode<K>, Bincode<V>>,
+    ) -> Result<u64, CakeError>
+    where
+        K: DbKey,
+        V: DbValue + Ord,
+    {
+        let moved: u64;
+
+        let transaction = self.inner.begin_write()?;
+        {
+            moved = copy_multimap_mappings(&transaction, from, to)?;
+            transaction.delete_multimap_table(from)?;
+        }
+        transaction.commit()?;
+
+        Ok(moved)
+    }
+
     /// Clears the contents of the given table, removing all key-value mappings.
     pub fn clear_multimap_table<K, V>(
         &mut self,
         table_def: MultimapTableDefinition<Bincode<K>, Bincode<V>>,
-    ) -> Result<(), Box<dyn std::error::Error>>
+    ) -> Result<(), CakeError>
     where
         K: DbKey,
         V: DbValue + Ord,
@@ -203,7 +474,7 @@ impl CakeDb {
     pub fn delete_multimap_table<K, V>(
         &mut self,
         table_def: MultimapTableDefinition<Bincode<K>, Bincode<V>>,
-    ) -> Result<bool, Box<dyn std::error::Error>>
+    ) -> Result<bool, CakeError>
     where
         K: DbKey,
         V: DbValue + Ord,
@@ -219,3 +490,29 @@ impl CakeDb {
         Ok(existed)
     }
 }
+
+/// Inserts every mapping of `from` into `to` within `transaction`, returning how many were copied.
+fn copy_multimap_mappings<K, V>(
+    transaction: &WriteTransaction,
+    from: MultimapTableDefinition<Bincode<K>, Bincode<V>>,
+    to: MultimapTableDefinition<Bincode<K>, Bincode<V>>,
+) -> Result<u64, CakeError>
+where
+    K: DbKey,
+    V: DbValue + Ord,
+{
+    let mut copied = 0;
+
+    let source = transaction.open_multimap_table(from)?;
+    let mut target = transaction.open_multimap_table(to)?;
+    for entry in source.iter()? {
+        let (key_guard, values) = entry?;
+        let key = key_guard.value();
+        for value in values {
+            target.insert(&key, value?.value())?;
+            copied += 1;
+        }
+    }
+
+    Ok(copied)
+}
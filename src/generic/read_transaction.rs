@@ -0,0 +1,85 @@
+use redb::{
+    MultimapTableDefinition, ReadOnlyMultimapTable, ReadOnlyTable, ReadTransaction,
+    ReadableDatabase, TableDefinition,
+};
+
+use crate::{bincode_wrapper::Bincode, error::CakeError, CakeDb};
+
+use super::{
+    internal::open_existing_table,
+    traits::{DbKey, DbValue},
+};
+
+/// A read-only view over a single consistent snapshot of the database.
+///
+/// Every table opened from the same `CakeReadTxn` reflects the same committed state,
+/// even if other writes are committed in the meantime.
+pub struct CakeReadTxn {
+    inner: ReadTransaction,
+}
+
+impl CakeDb {
+    /// Begins a read transaction, which can open several tables from one consistent snapshot.
+    pub fn read_transaction(&self) -> Result<CakeReadTxn, CakeError> {
+        Ok(CakeReadTxn {
+            inner: self.inner.begin_read()?,
+        })
+    }
+}
+
+impl CakeReadTxn {
+    /// Opens the given table as read-only.
+    ///
+    /// Unlike the read methods on [`CakeDb`], this returns an error if the table doesn't exist,
+    /// since a snapshot can't be modified to create it.
+    pub fn open_table<K, V>(
+        &self,
+        table_def: TableDefinition<Bincode<K>, Bincode<V>>,
+    ) -> Result<ReadOnlyTable<Bincode<K>, Bincode<V>>, CakeError>
+    where
+        K: DbKey,
+        V: DbValue,
+    {
+        Ok(self.inner.open_table(table_def)?)
+    }
+
+    /// Returns the value of `key` in the given table as of this snapshot, if it exists.
+    ///
+    /// Returns `None` if the table doesn't exist. Call this on several tables to read the same
+    /// key across them from one consistent snapshot, e.g. an id shared by several tables.
+    pub fn get<K, V>(
+        &self,
+        table_def: TableDefinition<Bincode<K>, Bincode<V>>,
+        key: &K,
+    ) -> Result<Option<V>, CakeError>
+    where
+        K: DbKey,
+        V: DbValue,
+    {
+        let Some(table) = open_existing_table(&self.inner, table_def)? else {
+            return Ok(None);
+        };
+        let value = table.get(key)?.map(|g| g.value());
+
+        Ok(value)
+    }
+
+    /// Opens the given multimap table as read-only.
+    ///
+    /// Returns an error if the table doesn't exist.
+    pub fn open_multimap_table<K, V>(
+        &self,
+        table_def: MultimapTableDefinition<Bincode<K>, Bincode<V>>,
+    ) -> Result<ReadOnlyMultimapTable<Bincode<K>, Bincode<V>>, CakeError>
+    where
+        K: DbKey,
+        V: DbValue + Ord,
+    {
+        Ok(self.inner.open_multimap_table(table_def)?)
+    }
+
+    /// Provides a reference to the inner `ReadTransaction`. Use this if you need finer control.
+    pub fn transaction(&self) -> &ReadTransaction {
+        &self.inner
+    }
+}
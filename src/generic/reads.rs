@@ -1,10 +1,30 @@
-use std::{collections::BTreeMap, ops::RangeBounds};
+use std::{
+    borrow::Borrow,
+    cmp::Ordering,
+    collections::{BTreeMap, HashMap},
+    hash::Hash,
+    ops::{Bound, RangeBounds},
+};
 
-use redb::{ReadableTable, TableDefinition};
+use bincode::{config, Encode};
+#[cfg(feature = "rayon")]
+use rayon::iter::{ParallelBridge, ParallelIterator};
+use redb::{
+    ReadableDatabase, ReadableTable, ReadableTableMetadata, TableDefinition, TableError,
+    TableHandle, Value,
+};
 
-use crate::{bincode_wrapper::Bincode, CakeDb};
+use crate::{
+    bincode_wrapper::{Bincode, RawBincode},
+    error::CakeError,
+    CakeDb,
+};
 
-use super::traits::{DbKey, DbValue};
+use super::{
+    copy::visit_raw_table,
+    internal::{checksum_table, open_existing_table, REVISIONS_TABLE},
+    traits::{DbKey, DbValue},
+};
 
 impl CakeDb {
     /// Returns the value if it exists.
@@ -12,7 +32,7 @@ impl CakeDb {
         &self,
         table_def: TableDefinition<Bincode<K>, Bincode<V>>,
         key: &K,
-    ) -> Result<Option<V>, Box<dyn std::error::Error>>
+    ) -> Result<Option<V>, CakeError>
     where
         K: DbKey,
         V: DbValue,
@@ -20,12 +40,89 @@ impl CakeDb {
         Ok(self.read_table(table_def)?.get(key)?.map(|g| g.value()))
     }
 
+    /// Decodes the value into `buf` if it exists, and returns whether it did.
+    ///
+    /// `buf` is left untouched if the key isn't found, so a single slot can be reused
+    /// across many lookups in a loop.
+    pub fn get_into<K, V>(
+        &self,
+        table_def: TableDefinition<Bincode<K>, Bincode<V>>,
+        key: &K,
+        buf: &mut Option<V>,
+    ) -> Result<bool, CakeError>
+    where
+        K: DbKey,
+        V: DbValue,
+    {
+        let Some(guard) = self.read_table(table_def)?.get(key)? else {
+            return Ok(false);
+        };
+        *buf = Some(guard.value());
+
+        Ok(true)
+    }
+
+    /// Returns the value if it exists, or `V::default()` otherwise.
+    ///
+    /// Nothing is written to the table when the key is missing.
+    pub fn get_or_default<K, V>(
+        &self,
+        table_def: TableDefinition<Bincode<K>, Bincode<V>>,
+        key: &K,
+    ) -> Result<V, CakeError>
+    where
+        K: DbKey,
+        V: DbValue + Default,
+    {
+        Ok(self.get(table_def, key)?.unwrap_or_default())
+    }
+
+    /// Returns the value of the given key if `key_predicate` accepts the key, or `None` otherwise.
+    ///
+    /// The table isn't touched at all when the predicate rejects the key.
+    pub fn get_if<K, V>(
+        &self,
+        table_def: TableDefinition<Bincode<K>, Bincode<V>>,
+        key: &K,
+        key_predicate: impl FnOnce(&K) -> bool,
+    ) -> Result<Option<V>, CakeError>
+    where
+        K: DbKey,
+        V: DbValue,
+    {
+        if !key_predicate(key) {
+            return Ok(None);
+        }
+
+        self.get(table_def, key)
+    }
+
+    /// Decodes the value of the given key and passes a reference to it to `f`, returning `f`'s result.
+    ///
+    /// Returns `None` if the key isn't present. [`Bincode`] always decodes into an owned value,
+    /// so this isn't zero-copy, but it lets callers extract just what they need from a large
+    /// value without moving it out or cloning parts of it.
+    pub fn with_value<K, V, R>(
+        &self,
+        table_def: TableDefinition<Bincode<K>, Bincode<V>>,
+        key: &K,
+        f: impl FnOnce(&V) -> R,
+    ) -> Result<Option<R>, CakeError>
+    where
+        K: DbKey,
+        V: DbValue,
+    {
+        let result = self.read_table(table_def)?.get(key)?.map(|g| f(&g.value()));
+
+        Ok(result)
+    }
+
     /// Returns `true` if the table contains the given key.
     pub fn contains_key<K, V>(
         &self,
         table_def: TableDefinition<Bincode<K>, Bincode<V>>,
         key: &K,
-    ) -> Result<bool, Box<dyn std::error::Error>>
+    ) -> Result<bool, CakeError>
     where
         K: DbKey,
         V: DbValue,
@@ -33,12 +130,82 @@ impl CakeDb {
         Ok(self.read_table(table_def)?.get(key)?.is_some())
     }
 
+    /// Returns the value if it exists, looking it up by a borrowed form of the key.
+    ///
+    /// This allows e.g. looking up a `String` key with a `&str`, without allocating.
+    /// `Q` must serialize to exactly the same bytes as the equivalent `K`, which holds for
+    /// `str`/`String` and `[T]`/`Vec<T>`, among others. Otherwise the key is simply not found.
+    pub fn get_borrowed<K, V, Q>(
+        &self,
+        table_def: TableDefinition<Bincode<K>, Bincode<V>>,
+        key: &Q,
+    ) -> Result<Option<V>, CakeError>
+    where
+        K: DbKey + Borrow<Q>,
+        V: DbValue,
+        Q: Encode + ?Sized,
+    {
+        let key = bincode::encode_to_vec(key, config::standard())?;
+        let Some(table) = self.open_raw_table(table_def)? else {
+            return Ok(None);
+        };
+        let value = table
+            .get(key.as_slice())?
+            .map(|g| Bincode::<V>::from_bytes(g.value()));
+
+        Ok(value)
+    }
+
+    /// Returns `true` if the table contains the given key, looking it up by a borrowed form of it.
+    ///
+    /// See [`get_borrowed`](Self::get_borrowed) for the constraints on `Q`.
+    pub fn contains_key_borrowed<K, V, Q>(
+        &self,
+        table_def: TableDefinition<Bincode<K>, Bincode<V>>,
+        key: &Q,
+    ) -> Result<bool, CakeError>
+    where
+        K: DbKey + Borrow<Q>,
+        V: DbValue,
+        Q: Encode + ?Sized,
+    {
+        let key = bincode::encode_to_vec(key, config::standard())?;
+        let Some(table) = self.open_raw_table(table_def)? else {
+            return Ok(false);
+        };
+        let exists = table.get(key.as_slice())?.is_some();
+
+        Ok(exists)
+    }
+
+    /// Reports whether the table contains each of the given keys, all read from the same snapshot.
+    pub fn which_exist<K, V, I>(
+        &self,
+        table_def: TableDefinition<Bincode<K>, Bincode<V>>,
+        keys: I,
+    ) -> Result<BTreeMap<K, bool>, CakeError>
+    where
+        K: DbKey,
+        V: DbValue,
+        I: IntoIterator<Item = K>,
+    {
+        let table = self.read_table(table_def)?;
+
+        let mut presence = BTreeMap::new();
+        for key in keys {
+            let exists = table.get(&key)?.is_some();
+            presence.insert(key, exists);
+        }
+
+        Ok(presence)
+    }
+
     /// Returns the first key-value pair matching the given predicate.
     pub fn find<K, V>(
         &self,
         table_def: TableDefinition<Bincode<K>, Bincode<V>>,
-        predicate: impl Fn(&K, &V) -> bool,
-    ) -> Result<Option<(K, V)>, Box<dyn std::error::Error>>
+        mut predicate: impl FnMut(&K, &V) -> bool,
+    ) -> Result<Option<(K, V)>, CakeError>
     where
         K: DbKey,
         V: DbValue,
@@ -53,8 +220,8 @@ impl CakeDb {
     pub fn rfind<K, V>(
         &self,
         table_def: TableDefinition<Bincode<K>, Bincode<V>>,
-        predicate: impl Fn(&K, &V) -> bool,
-    ) -> Result<Option<(K, V)>, Box<dyn std::error::Error>>
+        mut predicate: impl FnMut(&K, &V) -> bool,
+    ) -> Result<Option<(K, V)>, CakeError>
     where
         K: DbKey,
         V: DbValue,
@@ -74,8 +241,8 @@ impl CakeDb {
         &self,
         n: usize,
         table_def: TableDefinition<Bincode<K>, Bincode<V>>,
-        predicate: impl Fn(&K, &V) -> bool,
-    ) -> Result<Option<(K, V)>, Box<dyn std::error::Error>>
+        mut predicate: impl FnMut(&K, &V) -> bool,
+    ) -> Result<Option<(K, V)>, CakeError>
     where
         K: DbKey,
         V: DbValue,
@@ -96,8 +263,8 @@ impl CakeDb {
     pub fn count_matches<K, V>(
         &self,
         table_def: TableDefinition<Bincode<K>, Bincode<V>>,
-        predicate: impl Fn(&K, &V) -> bool,
-    ) -> Result<usize, Box<dyn std::error::Error>>
+        mut predicate: impl FnMut(&K, &V) -> bool,
+    ) -> Result<usize, CakeError>
     where
         K: DbKey,
         V: DbValue,
@@ -109,29 +276,196 @@ impl CakeDb {
             .count())
     }
 
+    /// Counts how many key-value pairs return `true` for the given predicate, on rayon's thread pool.
+    ///
+    /// The table is read once, from a single snapshot, as raw bytes; decoding each pair and
+    /// running `predicate` on it is spread across threads. The count is therefore exact for
+    /// that snapshot, even if other handles write to the table meanwhile.
+    /// Requires the `rayon` feature.
+    #[cfg(feature = "rayon")]
+    pub fn par_count_matches<K, V>(
+        &self,
+        table_def: TableDefinition<Bincode<K>, Bincode<V>>,
+        predicate: impl Fn(&K, &V) -> bool + Sync,
+    ) -> Result<usize, CakeError>
+    where
+        K: DbKey + Send,
+        V: DbValue + Send,
+    {
+        let Some(table) = self.open_raw_table(table_def)? else {
+            return Ok(0);
+        };
+
+        table
+            .iter()?
+            .par_bridge()
+            .map(|pair| {
+                let (kg, vg) = pair?;
+                let key = Bincode::<K>::try_from_bytes(kg.value())?;
+                let value = Bincode::<V>::try_from_bytes(vg.value())?;
+
+                Ok(usize::from(predicate(&key, &value)))
+            })
+            .try_reduce(|| 0, |a, b| Ok(a + b))
+    }
+
+    /// Counts how many key-value pairs return `true` for the given predicate, up to `cap`.
+    ///
+    /// The scan stops as soon as `cap` matches are found, which makes "more than N" checks cheap.
+    pub fn count_matches_capped<K, V>(
+        &self,
+        table_def: TableDefinition<Bincode<K>, Bincode<V>>,
+        mut predicate: impl FnMut(&K, &V) -> bool,
+        cap: usize,
+    ) -> Result<usize, CakeError>
+    where
+        K: DbKey,
+        V: DbValue,
+    {
+        let mut count = 0;
+        for pair in self.read_table(table_def)?.iter()? {
+            if count == cap {
+                break;
+            }
+            let (kg, vg) = pair?;
+            if predicate(&kg.value(), &vg.value()) {
+                count += 1;
+            }
+        }
+
+        Ok(count)
+    }
+
     /// Returns all key-value pairs that match the given predicate.
     pub fn filter<K, V>(
         &self,
         table_def: TableDefinition<Bincode<K>, Bincode<V>>,
-        predicate: impl Fn(&K, &V) -> bool,
-    ) -> Result<BTreeMap<K, V>, Box<dyn std::error::Error>>
+        predicate: impl FnMut(&K, &V) -> bool,
+    ) -> Result<BTreeMap<K, V>, CakeError>
     where
         K: DbKey,
         V: DbValue,
     {
-        Ok(self
-            .table(table_def)?
-            .into_iter()
-            .filter(|(k, v)| predicate(k, v))
-            .collect())
+        self.filter_into(table_def, predicate)
+    }
+
+    /// Collects all key-value pairs that match the given predicate into any collection, in key order.
+    ///
+    /// Pairs are collected straight from the table, e.g. into a `Vec` or a `HashMap`.
+    pub fn filter_into<K, V, C>(
+        &self,
+        table_def: TableDefinition<Bincode<K>, Bincode<V>>,
+        mut predicate: impl FnMut(&K, &V) -> bool,
+    ) -> Result<C, CakeError>
+    where
+        K: DbKey,
+        V: DbValue,
+        C: FromIterator<(K, V)>,
+    {
+        let table = self.read_table(table_def)?;
+        let collected = table
+            .iter()?
+            .map(|pair| pair.map(|(kg, vg)| (kg.value(), vg.value())))
+            .filter(|pair| pair.as_ref().map_or(true, |(k, v)| predicate(k, v)))
+            .collect::<Result<C, _>>()?;
+
+        Ok(collected)
+    }
+
+    /// Returns all key-value pairs that match the given predicate, sorted by `cmp`.
+    ///
+    /// The sort is stable, so pairs that compare equal stay in key order.
+    pub fn filter_sorted_by<K, V>(
+        &self,
+        table_def: TableDefinition<Bincode<K>, Bincode<V>>,
+        predicate: impl FnMut(&K, &V) -> bool,
+        cmp: impl FnMut(&(K, V), &(K, V)) -> Ordering,
+    ) -> Result<Vec<(K, V)>, CakeError>
+    where
+        K: DbKey,
+        V: DbValue,
+    {
+        let mut pairs: Vec<(K, V)> = self.filter_into(table_def, predicate)?;
+        pairs.sort_by(cmp);
+        Ok(pairs)
+    }
+
+    /// Returns all key-value pairs that match the given predicate, examining at most `max_examined` rows.
+    ///
+    /// Also returns `true` if the scan stopped before reaching the end of the table.
+    pub fn filter_limited<K, V>(
+        &self,
+        table_def: TableDefinition<Bincode<K>, Bincode<V>>,
+        mut predicate: impl FnMut(&K, &V) -> bool,
+        max_examined: usize,
+    ) -> Result<(BTreeMap<K, V>, bool), CakeError>
+    where
+        K: DbKey,
+        V: DbValue,
+    {
+        let mut matches = BTreeMap::new();
+        let mut examined = 0;
+
+        let table = self.read_table(table_def)?;
+        let mut iter = table.iter()?;
+        while examined < max_examined {
+            let Some(pair) = iter.next() else {
+                return Ok((matches, false));
+            };
+            let (kg, vg) = pair?;
+            let (key, value) = (kg.value(), vg.value());
+            if predicate(&key, &value) {
+                matches.insert(key, value);
+            }
+            examined += 1;
+        }
+
+        let truncated = iter.next().is_some();
+        Ok((matches, truncated))
+    }
+
+    /// Returns all key-value pairs that match the given predicate, skipping rows that fail to decode.
+    ///
+    /// Also returns the number of skipped rows, e.g. rows written with an incompatible schema.
+    pub fn filter_lenient<K, V>(
+        &self,
+        table_def: TableDefinition<Bincode<K>, Bincode<V>>,
+        mut predicate: impl FnMut(&K, &V) -> bool,
+    ) -> Result<(BTreeMap<K, V>, u64), CakeError>
+    where
+        K: DbKey,
+        V: DbValue,
+    {
+        let mut matches = BTreeMap::new();
+        let mut skipped = 0;
+
+        let Some(table) = self.open_raw_table(table_def)? else {
+            return Ok((matches, skipped));
+        };
+        for pair in table.iter()? {
+            let (kg, vg) = pair?;
+            let key = Bincode::<K>::try_from_bytes(kg.value());
+            let value = Bincode::<V>::try_from_bytes(vg.value());
+
+            match (key, value) {
+                (Ok(key), Ok(value)) => {
+                    if predicate(&key, &value) {
+                        matches.insert(key, value);
+                    }
+                }
+                _ => skipped += 1,
+            }
+        }
+
+        Ok((matches, skipped))
     }
 
     /// Returns all keys of the key-value pairs that match the given predicate.
     pub fn filter_keys<K, V>(
         &self,
         table_def: TableDefinition<Bincode<K>, Bincode<V>>,
-        predicate: impl Fn(&K, &V) -> bool,
-    ) -> Result<Vec<K>, Box<dyn std::error::Error>>
+        mut predicate: impl FnMut(&K, &V) -> bool,
+    ) -> Result<Vec<K>, CakeError>
     where
         K: DbKey,
         V: DbValue,
@@ -144,11 +478,33 @@ impl CakeDb {
             .collect())
     }
 
+    /// Applies `f` to every key-value pair and returns all the `Some` results.
+    ///
+    /// Unlike [`filter`](Self::filter), the table is scanned in a single pass without
+    /// collecting the matching pairs first.
+    pub fn filter_map<K, V, R>(
+        &self,
+        table_def: TableDefinition<Bincode<K>, Bincode<V>>,
+        f: impl Fn(&K, &V) -> Option<R>,
+    ) -> Result<Vec<R>, CakeError>
+    where
+        K: DbKey,
+        V: DbValue,
+    {
+        let mut results = Vec::new();
+        for pair in self.read_table(table_def)?.iter()? {
+            let (kg, vg) = pair?;
+            results.extend(f(&kg.value(), &vg.value()));
+        }
+
+        Ok(results)
+    }
+
     /// Returns all the key-value pairs in the given table.
     pub fn table<K, V>(
         &self,
         table_def: TableDefinition<Bincode<K>, Bincode<V>>,
-    ) -> Result<BTreeMap<K, V>, Box<dyn std::error::Error>>
+    ) -> Result<BTreeMap<K, V>, CakeError>
     where
         K: DbKey,
         V: DbValue,
@@ -161,64 +517,286 @@ impl CakeDb {
             .collect())
     }
 
-    /// Returns the first pair in the table.
-    pub fn first<K, V>(
+    /// Collects all the key-value pairs in the given table into any collection, in key order.
+    pub fn collect_into<K, V, C>(
+        &self,
+        table_def: TableDefinition<Bincode<K>, Bincode<V>>,
+    ) -> Result<C, CakeError>
+    where
+        K: DbKey,
+        V: DbValue,
+        C: FromIterator<(K, V)>,
+    {
+        self.filter_into(table_def, |_, _| true)
+    }
+
+    /// Returns all the key-value pairs in the given table as a `HashMap`.
+    ///
+    /// Prefer this over [`table`](Self::table) when the result is only used for random lookups.
+    pub fn table_hashmap<K, V>(
+        &self,
+        table_def: TableDefinition<Bincode<K>, Bincode<V>>,
+    ) -> Result<HashMap<K, V>, CakeError>
+    where
+        K: DbKey + Hash,
+        V: DbValue,
+    {
+        self.collect_into(table_def)
+    }
+
+    /// Returns all the key-value pairs in the given table as a `Vec`, in key order.
+    ///
+    /// The `Vec` is allocated up front from the table's length.
+    pub fn table_vec_presized<K, V>(
         &self,
         table_def: TableDefinition<Bincode<K>, Bincode<V>>,
-    ) -> Result<Option<(K, V)>, Box<dyn std::error::Error>>
+    ) -> Result<Vec<(K, V)>, CakeError>
+    where
+        K: DbKey,
+        V: DbValue,
+    {
+        let table = self.read_table(table_def)?;
+
+        let mut pairs = Vec::with_capacity(table.len()? as usize);
+        for pair in table.iter()? {
+            let (kg, vg) = pair?;
+            pairs.push((kg.value(), vg.value()));
+        }
+
+        Ok(pairs)
+    }
+
+    /// Returns an iterator over the key-value pairs of the given table, in ascending key order.
+    ///
+    /// Unlike [`table`](Self::table), nothing is collected: each pair is only decoded as the
+    /// iterator advances, so scanning a large table once, or just its first few pairs with
+    /// [`take`](Iterator::take), keeps memory use flat.
+    pub fn scan<K, V>(
+        &self,
+        table_def: TableDefinition<Bincode<K>, Bincode<V>>,
+    ) -> Result<impl Iterator<Item = Result<(K, V), CakeError>>, CakeError>
+    where
+        K: DbKey,
+        V: DbValue,
+    {
+        Ok(self.read_table(table_def)?.range::<K>(..)?.map(|pair| {
+            pair.map(|(kg, vg)| (kg.value(), vg.value()))
+                .map_err(Into::into)
+        }))
+    }
+
+    /// Returns an iterator over the keys of the given table, in ascending order.
+    ///
+    /// Values are never deserialized, which makes this much cheaper than [`table`](Self::table)
+    /// for tables with large values.
+    pub fn iter_keys<K, V>(
+        &self,
+        table_def: TableDefinition<Bincode<K>, Bincode<V>>,
+    ) -> Result<impl Iterator<Item = Result<K, CakeError>>, CakeError>
     where
         K: DbKey,
         V: DbValue,
     {
         Ok(self
             .read_table(table_def)?
-            .first()?
-            .map(|(kg, vg)| (kg.value(), vg.value())))
+            .range::<K>(..)?
+            .map(|pair| pair.map(|(kg, _)| kg.value()).map_err(Into::into)))
     }
 
-    /// Returns the last pair in the table.
-    pub fn last<K, V>(
+    /// Returns an iterator over the values of the given table, in ascending key order.
+    ///
+    /// Keys are never deserialized, and values are only decoded as the iterator advances.
+    pub fn iter_values<K, V>(
         &self,
         table_def: TableDefinition<Bincode<K>, Bincode<V>>,
-    ) -> Result<Option<(K, V)>, Box<dyn std::error::Error>>
+    ) -> Result<impl Iterator<Item = Result<V, CakeError>>, CakeError>
     where
         K: DbKey,
         V: DbValue,
     {
         Ok(self
             .read_table(table_def)?
-            .last()?
-            .map(|(kg, vg)| (kg.value(), vg.value())))
+            .range::<K>(..)?
+            .map(|pair| pair.map(|(_, vg)| vg.value()).map_err(Into::into)))
+    }
+
+    /// Returns the number of pairs in the given table, without deserializing any of them.
+    ///
+    /// Returns `0` without creating the table if it doesn't exist.
+    pub fn count<K, V>(
+        &self,
+        table_def: TableDefinition<Bincode<K>, Bincode<V>>,
+    ) -> Result<u64, CakeError>
+    where
+        K: DbKey,
+        V: DbValue,
+    {
+        match self.try_read_table(table_def)? {
+            Some(table) => Ok(table.len()?),
+            None => Ok(0),
+        }
+    }
+
+    /// Returns `true` if the given table holds no pairs, or doesn't exist.
+    pub fn is_empty<K, V>(
+        &self,
+        table_def: TableDefinition<Bincode<K>, Bincode<V>>,
+    ) -> Result<bool, CakeError>
+    where
+        K: DbKey,
+        V: DbValue,
+    {
+        Ok(self.count(table_def)? == 0)
+    }
+
+    /// Returns all keys of the given table, in ascending order.
+    ///
+    /// Values are never deserialized; see [`iter_keys`](Self::iter_keys) to avoid collecting the keys too.
+    pub fn keys<K, V>(
+        &self,
+        table_def: TableDefinition<Bincode<K>, Bincode<V>>,
+    ) -> Result<Vec<K>, CakeError>
+    where
+        K: DbKey,
+        V: DbValue,
+    {
+        self.iter_keys(table_def)?.collect()
+    }
+
+    /// Returns all values of the given table, in ascending key order.
+    ///
+    /// Keys are never deserialized; see [`iter_values`](Self::iter_values) to avoid collecting the values too.
+    pub fn values<K, V>(
+        &self,
+        table_def: TableDefinition<Bincode<K>, Bincode<V>>,
+    ) -> Result<Vec<V>, CakeError>
+    where
+        K: DbKey,
+        V: DbValue,
+    {
+        self.iter_values(table_def)?.collect()
+    }
+
+    /// Returns an iterator over the stored bytes of each key-value pair of the given table, in key order.
+    ///
+    /// Nothing is decoded, so this is useful for benchmarks, checksums, or custom codecs.
+    /// The bytes decode back with [`Bincode::try_from_bytes`].
+    #[allow(clippy::type_complexity)]
+    pub fn iter_raw<K, V>(
+        &self,
+        table_def: TableDefinition<Bincode<K>, Bincode<V>>,
+    ) -> Result<impl Iterator<Item = Result<(Vec<u8>, Vec<u8>), CakeError>>, CakeError>
+    where
+        K: DbKey,
+        V: DbValue,
+    {
+        let pairs = match self.open_raw_table(table_def)? {
+            Some(table) => Some(table.range::<&[u8]>(..)?),
+            None => None,
+        };
+
+        Ok(pairs.into_iter().flatten().map(|pair| {
+            pair.map(|(kg, vg)| (kg.value().to_vec(), vg.value().to_vec()))
+                .map_err(Into::into)
+        }))
+    }
+
+    /// Tallies the stored sizes of the table's values into buckets, without decoding them.
+    ///
+    /// Each value is counted under the smallest boundary in `buckets` that its size in bytes doesn't exceed.
+    /// Every boundary is present in the result, even with a count of zero. Values larger than all
+    /// boundaries are counted under `usize::MAX`, which only appears if there are any.
+    pub fn value_size_histogram<K, V>(
+        &self,
+        table_def: TableDefinition<Bincode<K>, Bincode<V>>,
+        buckets: &[usize],
+    ) -> Result<BTreeMap<usize, u64>, CakeError>
+    where
+        K: DbKey,
+        V: DbValue,
+    {
+        let mut histogram: BTreeMap<usize, u64> = buckets.iter().map(|&b| (b, 0)).collect();
+        for pair in self.iter_raw(table_def)? {
+            let size = pair?.1.len();
+            match histogram.range_mut(size..).next() {
+                Some((_, count)) => *count += 1,
+                None => *histogram.entry(usize::MAX).or_default() += 1,
+            }
+        }
+
+        Ok(histogram)
+    }
+
+    /// Returns the first pair in the table.
+    ///
+    /// Returns `None` without creating the table if it doesn't exist.
+    pub fn first<K, V>(
+        &self,
+        table_def: TableDefinition<Bincode<K>, Bincode<V>>,
+    ) -> Result<Option<(K, V)>, CakeError>
+    where
+        K: DbKey,
+        V: DbValue,
+    {
+        let Some(table) = self.try_read_table(table_def)? else {
+            return Ok(None);
+        };
+        let entry = table.first()?.map(|(kg, vg)| (kg.value(), vg.value()));
+        Ok(entry)
+    }
+
+    /// Returns the last pair in the table.
+    ///
+    /// Returns `None` without creating the table if it doesn't exist.
+    pub fn last<K, V>(
+        &self,
+        table_def: TableDefinition<Bincode<K>, Bincode<V>>,
+    ) -> Result<Option<(K, V)>, CakeError>
+    where
+        K: DbKey,
+        V: DbValue,
+    {
+        let Some(table) = self.try_read_table(table_def)? else {
+            return Ok(None);
+        };
+        let entry = table.last()?.map(|(kg, vg)| (kg.value(), vg.value()));
+        Ok(entry)
     }
 
     /// Returns the first key in the given table.
+    ///
+    /// Returns `None` without creating the table if it doesn't exist.
     pub fn first_key<K, V>(
         &self,
         table_def: TableDefinition<Bincode<K>, Bincode<V>>,
-    ) -> Result<Option<K>, Box<dyn std::error::Error>>
+    ) -> Result<Option<K>, CakeError>
     where
         K: DbKey,
         V: DbValue,
     {
-        Ok(self
-            .read_table(table_def)?
-            .first()?
-            .map(|(kg, _)| kg.value()))
+        let Some(table) = self.try_read_table(table_def)? else {
+            return Ok(None);
+        };
+        let entry = table.first()?.map(|(kg, _)| kg.value());
+        Ok(entry)
     }
 
     /// Returns the last key in the given table.
+    ///
+    /// Returns `None` without creating the table if it doesn't exist.
     pub fn last_key<K, V>(
         &self,
         table_def: TableDefinition<Bincode<K>, Bincode<V>>,
-    ) -> Result<Option<K>, Box<dyn std::error::Error>>
+    ) -> Result<Option<K>, CakeError>
     where
         K: DbKey,
         V: DbValue,
     {
-        Ok(self
-            .read_table(table_def)?
-            .last()?
-            .map(|(kg, _)| kg.value()))
+        let Some(table) = self.try_read_table(table_def)? else {
+            return Ok(None);
+        };
+        let entry = table.last()?.map(|(kg, _)| kg.value());
+        Ok(entry)
     }
 
     /// Returns all key-value pairs in the given range of keys
@@ -226,7 +804,7 @@ impl CakeDb {
         &self,
         table_def: TableDefinition<Bincode<K>, Bincode<V>>,
         range: impl RangeBounds<K>,
-    ) -> Result<BTreeMap<K, V>, Box<dyn std::error::Error>>
+    ) -> Result<BTreeMap<K, V>, CakeError>
     where
         K: DbKey,
         V: DbValue,
@@ -238,4 +816,446 @@ impl CakeDb {
             .map(|(kg, vg)| (kg.value(), vg.value()))
             .collect())
     }
+
+    /// Returns the keys and values in the given range of keys as two vectors, aligned by index.
+    pub fn range_columns<K, V>(
+        &self,
+        table_def: TableDefinition<Bincode<K>, Bincode<V>>,
+        range: impl RangeBounds<K>,
+    ) -> Result<(Vec<K>, Vec<V>), CakeError>
+    where
+        K: DbKey,
+        V: DbValue,
+    {
+        let table = self.read_table(table_def)?;
+        let columns = table
+            .range(range)?
+            .map(|pair| pair.map(|(kg, vg)| (kg.value(), vg.value())))
+            .collect::<Result<_, _>>()?;
+
+        Ok(columns)
+    }
+
+    /// Returns all key-value pairs with keys between `lo` and `hi`, both inclusive.
+    ///
+    /// Unlike [`range`](Self::range), which silently returns nothing for a reversed range,
+    /// this returns an error if `lo` is greater than `hi`.
+    pub fn range_inclusive<K, V>(
+        &self,
+        table_def: TableDefinition<Bincode<K>, Bincode<V>>,
+        lo: K,
+        hi: K,
+    ) -> Result<BTreeMap<K, V>, CakeError>
+    where
+        K: DbKey,
+        V: DbValue,
+    {
+        if lo > hi {
+            return Err(CakeError::InvalidRange(format!(
+                "{lo:?} is greater than {hi:?}"
+            )));
+        }
+
+        self.range(table_def, lo..=hi)
+    }
+
+    /// Returns an iterator over the key-value pairs in the given range of keys, in chunks of up to `chunk_size` pairs.
+    ///
+    /// Pairs are only read as the iterator advances, so at most one chunk is held in memory at a time.
+    /// Returns an error if `chunk_size` is zero.
+    #[allow(clippy::type_complexity)]
+    pub fn range_chunks<K, V>(
+        &self,
+        table_def: TableDefinition<Bincode<K>, Bincode<V>>,
+        range: impl RangeBounds<K>,
+        chunk_size: usize,
+    ) -> Result<impl Iterator<Item = Result<Vec<(K, V)>, CakeError>>, CakeError>
+    where
+        K: DbKey,
+        V: DbValue,
+    {
+        if chunk_size == 0 {
+            return Err(CakeError::InvalidArgument(
+                "chunk size must be greater than zero".to_string(),
+            ));
+        }
+
+        let mut pairs = self.read_table(table_def)?.range(range)?;
+        Ok(std::iter::from_fn(move || {
+            let mut chunk = Vec::with_capacity(chunk_size);
+            for pair in pairs.by_ref().take(chunk_size) {
+                match pair {
+                    Ok((kg, vg)) => chunk.push((kg.value(), vg.value())),
+                    Err(e) => return Some(Err(e.into())),
+                }
+            }
+
+            (!chunk.is_empty()).then_some(Ok(chunk))
+        }))
+    }
+
+    /// Returns all key-value pairs in any of the given ranges of keys.
+    ///
+    /// All ranges are read from the same snapshot. Pairs in overlapping ranges are only returned once.
+    pub fn multi_range<K, V>(
+        &self,
+        table_def: TableDefinition<Bincode<K>, Bincode<V>>,
+        ranges: &[(Bound<K>, Bound<K>)],
+    ) -> Result<BTreeMap<K, V>, CakeError>
+    where
+        K: DbKey,
+        V: DbValue,
+    {
+        let table = self.read_table(table_def)?;
+
+        let mut pairs = BTreeMap::new();
+        for (start, end) in ranges {
+            for pair in table.range::<K>((start.as_ref(), end.as_ref()))? {
+                let (kg, vg) = pair?;
+                pairs.insert(kg.value(), vg.value());
+            }
+        }
+
+        Ok(pairs)
+    }
+
+    /// Returns all key-value pairs in the given range of keys that match the given predicate.
+    ///
+    /// Only keys within the range are visited.
+    pub fn range_filter<K, V>(
+        &self,
+        table_def: TableDefinition<Bincode<K>, Bincode<V>>,
+        range: impl RangeBounds<K>,
+        mut predicate: impl FnMut(&K, &V) -> bool,
+    ) -> Result<BTreeMap<K, V>, CakeError>
+    where
+        K: DbKey,
+        V: DbValue,
+    {
+        let mut matches = BTreeMap::new();
+        for pair in self.read_table(table_def)?.range(range)? {
+            let (kg, vg) = pair?;
+            let (key, value) = (kg.value(), vg.value());
+            if predicate(&key, &value) {
+                matches.insert(key, value);
+            }
+        }
+
+        Ok(matches)
+    }
+
+    /// Counts how many keys start with each of the given prefixes.
+    ///
+    /// Each prefix is counted with a range scan starting at the prefix, so only matching
+    /// keys are visited and values are never deserialized.
+    pub fn prefix_counts<V>(
+        &self,
+        table_def: TableDefinition<Bincode<String>, Bincode<V>>,
+        prefixes: &[&str],
+    ) -> Result<BTreeMap<String, u64>, CakeError>
+    where
+        V: DbValue,
+    {
+        let table = self.read_table(table_def)?;
+
+        let mut counts = BTreeMap::new();
+        for prefix in prefixes {
+            let mut count = 0;
+            for pair in table.range(prefix.to_string()..)? {
+                if !pair?.0.value().starts_with(prefix) {
+                    break;
+                }
+                count += 1;
+            }
+            counts.insert(prefix.to_string(), count);
+        }
+
+        Ok(counts)
+    }
+
+    /// Counts the keys falling into each of `num_buckets` consecutive buckets of `bucket_width`, starting at `start`.
+    ///
+    /// Meant for timestamp keys, e.g. to chart how many rows were written per hour.
+    /// Only the keys in `start..start + bucket_width * num_buckets` are visited, and values are never deserialized.
+    /// Returns an error if `bucket_width` isn't positive.
+    pub fn bucket_counts<V>(
+        &self,
+        table_def: TableDefinition<Bincode<i64>, Bincode<V>>,
+        start: i64,
+        bucket_width: i64,
+        num_buckets: usize,
+    ) -> Result<Vec<u64>, CakeError>
+    where
+        V: DbValue,
+    {
+        if bucket_width <= 0 {
+            return Err(CakeError::InvalidArgument(
+                "bucket width must be greater than zero".to_string(),
+            ));
+        }
+
+        let span = i64::try_from(num_buckets)
+            .ok()
+            .and_then(|n| n.checked_mul(bucket_width));
+        let end = span.map_or(Bound::Unbounded, |span| match start.checked_add(span) {
+            Some(end) => Bound::Excluded(end),
+            None => Bound::Unbounded,
+        });
+
+        let table = self.read_table(table_def)?;
+        let mut counts = vec![0; num_buckets];
+        for pair in table.range::<i64>((Bound::Included(start), end))? {
+            let offset = i128::from(pair?.0.value()) - i128::from(start);
+            let bucket = (offset / i128::from(bucket_width)) as usize;
+            let Some(count) = counts.get_mut(bucket) else {
+                break;
+            };
+            *count += 1;
+        }
+
+        Ok(counts)
+    }
+
+    /// Groups keys that store identical values, keyed by the serialized value.
+    ///
+    /// Values are compared by their stored bytes, without decoding them.
+    /// Only groups with more than one key are returned, each with its keys in ascending order.
+    pub fn find_duplicate_values<K, V>(
+        &self,
+        table_def: TableDefinition<Bincode<K>, Bincode<V>>,
+    ) -> Result<BTreeMap<Vec<u8>, Vec<K>>, CakeError>
+    where
+        K: DbKey,
+        V: DbValue,
+    {
+        let read = self.inner.begin_read()?;
+        let raw_def: TableDefinition<Bincode<K>, RawBincode<V>> =
+            TableDefinition::new(table_def.name());
+        let Some(table) = open_existing_table(&read, raw_def)? else {
+            return Ok(BTreeMap::new());
+        };
+
+        let mut groups: BTreeMap<Vec<u8>, Vec<K>> = BTreeMap::new();
+        for pair in table.iter()? {
+            let (kg, vg) = pair?;
+            groups
+                .entry(vg.value().to_vec())
+                .or_default()
+                .push(kg.value());
+        }
+        groups.retain(|_, keys| keys.len() > 1);
+
+        Ok(groups)
+    }
+
+    /// Groups the keys of the table by their value, each group with its keys in ascending order.
+    ///
+    /// Every distinct value is held in memory as a map key, so for large value types this
+    /// can cost about as much memory as loading the whole table.
+    pub fn invert<K, V>(
+        &self,
+        table_def: TableDefinition<Bincode<K>, Bincode<V>>,
+    ) -> Result<BTreeMap<V, Vec<K>>, CakeError>
+    where
+        K: DbKey,
+        V: DbValue + Ord,
+    {
+        let mut groups: BTreeMap<V, Vec<K>> = BTreeMap::new();
+        for pair in self.read_table(table_def)?.iter()? {
+            let (kg, vg) = pair?;
+            groups.entry(vg.value()).or_default().push(kg.value());
+        }
+
+        Ok(groups)
+    }
+
+    /// Returns the names of all tables in the database, excluding multimap tables.
+    pub fn list_tables(&self) -> Result<Vec<String>, CakeError> {
+        Ok(self
+            .inner
+            .begin_read()?
+            .list_tables()?
+            .map(|handle| handle.name().to_string())
+            .filter(|name| name != REVISIONS_TABLE.name())
+            .collect())
+    }
+
+    /// Returns the stored bytes of every key-value pair of the table `name`, in key order.
+    ///
+    /// The table is looked up by name at runtime, so its key and value types don't need to be
+    /// known; this lets tooling like an inspector dump any table from [`list_tables`](Self::list_tables).
+    /// The bytes of [`Bincode`] tables decode back with [`Bincode::try_from_bytes`].
+    /// Returns an error if there's no table named `name`.
+    #[allow(clippy::type_complexity)]
+    pub fn read_raw_table(&self, name: &str) -> Result<Vec<(Vec<u8>, Vec<u8>)>, CakeError> {
+        let mut pairs = Vec::new();
+        let types = visit_raw_table(&self.inner.begin_read()?, name, |key, value| {
+            pairs.push((key.to_vec(), value.to_vec()));
+            Ok(())
+        })?;
+        if types.is_none() {
+            return Err(TableError::TableDoesNotExist(name.to_string()).into());
+        }
+
+        Ok(pairs)
+    }
+
+    /// Returns `true` if no table or multimap table in the database holds any data.
+    pub fn database_is_empty(&self) -> Result<bool, CakeError> {
+        let read = self.inner.begin_read()?;
+
+        for handle in read.list_tables()? {
+            if handle.name() == REVISIONS_TABLE.name() {
+                continue;
+            }
+            if !read.open_untyped_table(handle)?.is_empty()? {
+                return Ok(false);
+            }
+        }
+        for handle in read.list_multimap_tables()? {
+            if !read.open_untyped_multimap_table(handle)?.is_empty()? {
+                return Ok(false);
+            }
+        }
+
+        Ok(true)
+    }
+
+    /// Returns the revision of the given table, which increases with every committed write to it.
+    ///
+    /// Compare it with a previously seen revision to cheaply tell whether the table changed.
+    /// Writes that change nothing, like removing a missing key, leave the revision as is.
+    /// Revisions are persisted, so they survive restarts. Restoring a savepoint or checkpoint
+    /// counts as a write to every table, so revisions never go back. Returns `0` for a
+    /// never-written table. Multimap tables don't have revisions: their writes aren't recorded.
+    pub fn table_revision<K, V>(
+        &self,
+        table_def: TableDefinition<Bincode<K>, Bincode<V>>,
+    ) -> Result<u64, CakeError>
+    where
+        K: DbKey,
+        V: DbValue,
+    {
+        let read = self.inner.begin_read()?;
+        let Some(revisions) = open_existing_table(&read, REVISIONS_TABLE)? else {
+            return Ok(0);
+        };
+        let revision = revisions.get(table_def.name())?.map_or(0, |g| g.value());
+
+        Ok(revision)
+    }
+
+    /// Returns `true` if the stored keys of the given table come out in non-decreasing `Ord` order.
+    ///
+    /// [`Bincode`] keys are ordered by decoding them and comparing with `Ord`, not by their
+    /// encoded bytes, so any key type with a consistent total order is safe, including integers,
+    /// `String`, and tuples or derived types built from them. This catches tables written while
+    /// the key type's `Ord` was different, e.g. before a field reorder, which breaks range queries.
+    pub fn verify_key_ordering<K, V>(
+        &self,
+        table_def: TableDefinition<Bincode<K>, Bincode<V>>,
+    ) -> Result<bool, CakeError>
+    where
+        K: DbKey,
+        V: DbValue,
+    {
+        let mut previous: Option<K> = None;
+        for key in self.iter_keys(table_def)? {
+            let key = key?;
+            if previous.as_ref().is_some_and(|previous| *previous > key) {
+                return Ok(false);
+            }
+            previous = Some(key);
+        }
+
+        Ok(true)
+    }
+
+    /// Returns a checksum of the stored keys and values of the given table.
+    ///
+    /// Equal contents always give equal checksums within the same build of the crate, so this
+    /// can cheaply tell whether two states of a table differ. It isn't stable across Rust
+    /// versions, so don't persist it.
+    pub fn table_checksum<K, V>(
+        &self,
+        table_def: TableDefinition<Bincode<K>, Bincode<V>>,
+    ) -> Result<u64, CakeError>
+    where
+        K: DbKey,
+        V: DbValue,
+    {
+        checksum_table(&self.inner.begin_read()?, table_def)
+    }
+
+    /// Returns the keys present in both tables, in ascending order.
+    ///
+    /// Both key streams are merged in order under a single read transaction, so neither
+    /// key set is loaded into memory as a whole.
+    pub fn intersect_keys<K, V1, V2>(
+        &self,
+        a: TableDefinition<Bincode<K>, Bincode<V1>>,
+        b: TableDefinition<Bincode<K>, Bincode<V2>>,
+    ) -> Result<Vec<K>, CakeError>
+    where
+        K: DbKey,
+        V1: DbValue,
+        V2: DbValue,
+    {
+        self.merge_keys(a, b, true)
+    }
+
+    /// Returns the keys present in `a` but not in `b`, in ascending order.
+    ///
+    /// Like [`intersect_keys`](Self::intersect_keys), this is an ordered merge under a single read transaction.
+    pub fn difference_keys<K, V1, V2>(
+        &self,
+        a: TableDefinition<Bincode<K>, Bincode<V1>>,
+        b: TableDefinition<Bincode<K>, Bincode<V2>>,
+    ) -> Result<Vec<K>, CakeError>
+    where
+        K: DbKey,
+        V1: DbValue,
+        V2: DbValue,
+    {
+        self.merge_keys(a, b, false)
+    }
+
+    /// Returns the keys of `a` that are (if `in_b`) or aren't (if not `in_b`) also keys of `b`.
+    fn merge_keys<K, V1, V2>(
+        &self,
+        a: TableDefinition<Bincode<K>, Bincode<V1>>,
+        b: TableDefinition<Bincode<K>, Bincode<V2>>,
+        in_b: bool,
+    ) -> Result<Vec<K>, CakeError>
+    where
+        K: DbKey,
+        V1: DbValue,
+        V2: DbValue,
+    {
+        let read = self.inner.begin_read()?;
+        let Some(table_a) = open_existing_table(&read, a)? else {
+            return Ok(Vec::new());
+        };
+        let table_b = open_existing_table(&read, b)?;
+
+        let range_b = table_b.as_ref().map(|table| table.iter()).transpose()?;
+        let mut keys_b = range_b
+            .into_iter()
+            .flatten()
+            .map(|pair| pair.map(|(kg, _)| kg.value()));
+        let mut next_b = keys_b.next().transpose()?;
+
+        let mut keys = Vec::new();
+        for pair in table_a.iter()? {
+            let key = pair?.0.value();
+            while next_b.as_ref().is_some_and(|key_b| *key_b < key) {
+                next_b = keys_b.next().transpose()?;
+            }
+            if (next_b.as_ref() == Some(&key)) == in_b {
+                keys.push(key);
+            }
+        }
+
+        Ok(keys)
+    }
 }
@@ -0,0 +1,118 @@
+use redb::{ReadableTable, TableDefinition, TableHandle, WriteTransaction};
+
+use crate::{bincode_wrapper::Bincode, error::CakeError, CakeDb};
+
+use super::traits::{DbKey, DbValue};
+
+/// A write transaction spanning several operations, possibly across tables.
+///
+/// Created by [`CakeDb::transaction`]. Either every write made through it is committed, or none is.
+pub struct CakeWriteTxn<'a> {
+    db: &'a CakeDb,
+    inner: WriteTransaction,
+    on_commit: Vec<Box<dyn FnOnce()>>,
+}
+
+impl CakeDb {
+    /// Runs `f` inside a single write transaction.
+    ///
+    /// The transaction is committed if `f` returns `Ok`, and aborted if it returns `Err`.
+    /// Closures registered with [`CakeWriteTxn::on_commit`] run, in registration order,
+    /// only once the commit has succeeded.
+    pub fn transaction<R>(
+        &mut self,
+        f: impl FnOnce(&mut CakeWriteTxn<'_>) -> Result<R, CakeError>,
+    ) -> Result<R, CakeError> {
+        let mut txn = CakeWriteTxn {
+            db: self,
+            inner: self.inner.begin_write()?,
+            on_commit: Vec::new(),
+        };
+
+        let result = match f(&mut txn) {
+            Ok(result) => result,
+            Err(e) => {
+                txn.inner.abort()?;
+                return Err(e);
+            }
+        };
+        txn.inner.commit()?;
+
+        for hook in txn.on_commit {
+            hook();
+        }
+
+        Ok(result)
+    }
+}
+
+impl CakeWriteTxn<'_> {
+    /// Registers `hook` to run after the transaction commits.
+    ///
+    /// Hooks never run if the transaction is aborted or fails to commit,
+    /// so they're a safe place for external side effects like enqueueing a message.
+    pub fn on_commit(&mut self, hook: impl FnOnce() + 'static) {
+        self.on_commit.push(Box::new(hook));
+    }
+
+    /// Returns the value of `key` in the given table, including writes made earlier in this transaction.
+    pub fn get<K, V>(
+        &self,
+        table_def: TableDefinition<Bincode<K>, Bincode<V>>,
+        key: &K,
+    ) -> Result<Option<V>, CakeError>
+    where
+        K: DbKey,
+        V: DbValue,
+    {
+        let table = self.inner.open_table(table_def)?;
+        let value = table.get(key)?.map(|g| g.value());
+
+        Ok(value)
+    }
+
+    /// Inserts a key-value pair into the table.
+    ///
+    /// Returns the old value.
+    pub fn insert<K, V>(
+        &mut self,
+        table_def: TableDefinition<Bincode<K>, Bincode<V>>,
+        key: &K,
+        value: V,
+    ) -> Result<Option<V>, CakeError>
+    where
+        K: DbKey,
+        V: DbValue,
+    {
+        let old_value = self
+            .inner
+            .open_table(table_def)?
+            .insert(key, value)?
+            .map(|g| g.value());
+        self.db.record_write(&self.inner, table_def.name())?;
+
+        Ok(old_value)
+    }
+
+    /// Removes a key from the table.
+    ///
+    /// Returns the old value.
+    pub fn remove<K, V>(
+        &mut self,
+        table_def: TableDefinition<Bincode<K>, Bincode<V>>,
+        key: &K,
+    ) -> Result<Option<V>, CakeError>
+    where
+        K: DbKey,
+        V: DbValue,
+    {
+        let old_value = self
+            .inner
+            .open_table(table_def)?
+            .remove(key)?
+            .map(|g| g.value());
+        self.db.record_write(&self.inner, table_def.name())?;
+
+        Ok(old_value)
+    }
+}
@@ -1,8 +1,15 @@
-use redb::{ReadableTable, TableDefinition};
+use redb::{ReadableTable, TableDefinition, TableHandle, Value};
 
-use crate::{bincode_wrapper::Bincode, CakeDb};
+use crate::{
+    bincode_wrapper::{Bincode, RawBincode},
+    error::CakeError,
+    CakeDb,
+};
 
-use super::traits::{DbKey, DbValue};
+use super::{
+    internal::encode_pair,
+    traits::{DbKey, DbValue},
+};
 
 impl CakeDb {
     /// Tries to add a key-value pair to the table.
@@ -15,7 +22,7 @@ impl CakeDb {
         table_def: TableDefinition<Bincode<K>, Bincode<V>>,
         key: &K,
         value: V,
-    ) -> Result<bool, Box<dyn std::error::Error>>
+    ) -> Result<bool, CakeError>
     where
         K: DbKey,
         V: DbValue,
@@ -33,6 +40,9 @@ impl CakeDb {
                 newly_added = false;
             }
         }
+        if newly_added {
+            self.record_write(&transaction, table_def.name())?;
+        }
         transaction.commit()?;
 
         Ok(newly_added)
@@ -43,28 +53,174 @@ impl CakeDb {
     /// If the map had this key present, its value will be overwritten by the new value.
     ///
     /// Returns the old value.
+    ///
+    /// Returns an `Err` without touching the table if the key or value can't be serialized.
     pub fn insert<K, V>(
         &mut self,
         table_def: TableDefinition<Bincode<K>, Bincode<V>>,
         key: &K,
         value: V,
-    ) -> Result<Option<V>, Box<dyn std::error::Error>>
+    ) -> Result<Option<V>, CakeError>
     where
         K: DbKey,
         V: DbValue,
     {
+        let (key, value) = encode_pair(key, &value)?;
+        let raw_def: TableDefinition<RawBincode<K>, RawBincode<V>> =
+            TableDefinition::new(table_def.name());
         let old_value: Option<V>;
 
         let transaction = self.inner.begin_write()?;
         {
-            let mut table = transaction.open_table(table_def)?;
-            old_value = table.insert(key, value)?.map(|guard| guard.value());
+            let mut table = transaction.open_table(raw_def)?;
+            old_value = table
+                .insert(key.as_slice(), value.as_slice())?
+                .map(|guard| Bincode::<V>::from_bytes(guard.value()));
         }
+        self.record_write(&transaction, table_def.name())?;
         transaction.commit()?;
 
         Ok(old_value)
     }
 
+    /// Inserts a key-value pair into the table, like [`insert`](Self::insert).
+    ///
+    /// Returns the old value, or `V::default()` if the key wasn't present.
+    pub fn replace_or_default<K, V>(
+        &mut self,
+        table_def: TableDefinition<Bincode<K>, Bincode<V>>,
+        key: &K,
+        value: V,
+    ) -> Result<V, CakeError>
+    where
+        K: DbKey,
+        V: DbValue + Default,
+    {
+        Ok(self.insert(table_def, key, value)?.unwrap_or_default())
+    }
+
+    /// Inserts a key-value pair into the table, unless the key already holds an equal value.
+    ///
+    /// Returns whether a write happened. Skipped writes don't commit anything, so they also
+    /// leave the [`table_revision`](Self::table_revision) unchanged.
+    pub fn insert_if_changed<K, V>(
+        &mut self,
+        table_def: TableDefinition<Bincode<K>, Bincode<V>>,
+        key: &K,
+        value: V,
+    ) -> Result<bool, CakeError>
+    where
+        K: DbKey,
+        V: DbValue + PartialEq,
+    {
+        let transaction = self.inner.begin_write()?;
+        {
+            let mut table = transaction.open_table(table_def)?;
+
+            if table.get(key)?.is_some_and(|g| g.value() == value) {
+                return Ok(false);
+            }
+            table.insert(key, value)?;
+        }
+        self.record_write(&transaction, table_def.name())?;
+        transaction.commit()?;
+
+        Ok(true)
+    }
+
+    /// Returns the value of the given key, or inserts and returns the value produced by `make`.
+    ///
+    /// `make` only runs if the key isn't present. If it fails, nothing is inserted and its error
+    /// is returned in the inner `Result`, keeping it apart from database errors.
+    pub fn get_or_try_insert_with<K, V, E>(
+        &mut self,
+        table_def: TableDefinition<Bincode<K>, Bincode<V>>,
+        key: &K,
+        make: impl FnOnce() -> Result<V, E>,
+    ) -> Result<Result<V, E>, CakeError>
+    where
+        K: DbKey,
+        V: DbValue,
+    {
+        let value: V;
+
+        let transaction = self.inner.begin_write()?;
+        {
+            let mut table = transaction.open_table(table_def)?;
+
+            if let Some(cached) = table.get(key)? {
+                return Ok(Ok(cached.value()));
+            }
+            value = match make() {
+                Ok(value) => value,
+                Err(e) => return Ok(Err(e)),
+            };
+            table.insert(key, &value)?;
+        }
+        self.record_write(&transaction, table_def.name())?;
+        transaction.commit()?;
+
+        Ok(Ok(value))
+    }
+
+    /// Appends a value under the next sequential key, and returns that key.
+    ///
+    /// The key is one past the current last key, or `0` for an empty table. Finding the last key
+    /// and inserting happen in the same transaction, so concurrent pushes never share a key.
+    pub fn push<V>(
+        &mut self,
+        table_def: TableDefinition<Bincode<u64>, Bincode<V>>,
+        value: V,
+    ) -> Result<u64, CakeError>
+    where
+        V: DbValue,
+    {
+        let key: u64;
+
+        let transaction = self.inner.begin_write()?;
+        {
+            let mut table = transaction.open_table(table_def)?;
+
+            key = match table.last()? {
+                Some((last, _)) => last
+                    .value()
+                    .checked_add(1)
+                    .ok_or(CakeError::KeySpaceExhausted)?,
+                None => 0,
+            };
+            table.insert(&key, value)?;
+        }
+        self.record_write(&transaction, table_def.name())?;
+        transaction.commit()?;
+
+        Ok(key)
+    }
+
+    /// Appends `item` to the `Vec` stored at `key`, starting a new `Vec` if the key isn't present.
+    pub fn push_to_vec<K, T>(
+        &mut self,
+        table_def: TableDefinition<Bincode<K>, Bincode<Vec<T>>>,
+        key: &K,
+        item: T,
+    ) -> Result<(), CakeError>
+    where
+        K: DbKey,
+        Vec<T>: DbValue,
+    {
+        let transaction = self.inner.begin_write()?;
+        {
+            let mut table = transaction.open_table(table_def)?;
+
+            let mut items = table.get(key)?.map(|g| g.value()).unwrap_or_default();
+            items.push(item);
+            table.insert(key, items)?;
+        }
+        self.record_write(&transaction, table_def.name())?;
+        transaction.commit()?;
+
+        Ok(())
+    }
+
     /// Applies `edit` to the given entry, replacing the old value.
     ///
     /// Returns the old value.
@@ -75,12 +231,11 @@ impl CakeDb {
         table_def: TableDefinition<Bincode<K>, Bincode<V>>,
         key: &K,
         mut edit: impl FnMut(&mut V),
-    ) -> Result<V, Box<dyn std::error::Error>>
+    ) -> Result<V, CakeError>
     where
         K: DbKey,
         V: DbValue,
     {
-        let key_not_found_error = anyhow::anyhow!("edit error: key not found in table");
         let old_value: V;
 
         let transaction = self.inner.begin_write()?;
@@ -90,7 +245,7 @@ impl CakeDb {
             let mut edited: V;
             {
                 let Some(value) = table.get(key)? else {
-                    return Err(key_not_found_error.into());
+                    return Err(CakeError::KeyNotFound);
                 };
                 edited = value.value();
                 edit(&mut edited)
@@ -99,14 +254,84 @@ impl CakeDb {
             let insert = table.insert(key, edited)?;
             old_value = match insert {
                 Some(value) => value.value(),
-                None => return Err(key_not_found_error.into()),
+                None => return Err(CakeError::KeyNotFound),
             }
         }
+        self.record_write(&transaction, table_def.name())?;
         transaction.commit()?;
 
         Ok(old_value)
     }
 
+    /// Applies `edit` to the given entry, replacing the old value.
+    ///
+    /// Returns whatever `edit` returned, or `None` if the key isn't found in the given table.
+    pub fn modify<K, V, R>(
+        &mut self,
+        table_def: TableDefinition<Bincode<K>, Bincode<V>>,
+        key: &K,
+        edit: impl FnOnce(&mut V) -> R,
+    ) -> Result<Option<R>, CakeError>
+    where
+        K: DbKey,
+        V: DbValue,
+    {
+        let result: R;
+
+        let transaction = self.inner.begin_write()?;
+        {
+            let mut table = transaction.open_table(table_def)?;
+
+            let mut edited = match table.get(key)? {
+                Some(value) => value.value(),
+                None => return Ok(None),
+            };
+            result = edit(&mut edited);
+            table.insert(key, edited)?;
+        }
+        self.record_write(&transaction, table_def.name())?;
+        transaction.commit()?;
+
+        Ok(Some(result))
+    }
+
+    /// Applies `edit` to the given entry, replacing the old value.
+    ///
+    /// Returns `false` if the key isn't found in the given table, unlike [`update`](Self::update),
+    /// which treats that as an error.
+    pub fn try_update<K, V>(
+        &mut self,
+        table_def: TableDefinition<Bincode<K>, Bincode<V>>,
+        key: &K,
+        edit: impl FnOnce(&mut V),
+    ) -> Result<bool, CakeError>
+    where
+        K: DbKey,
+        V: DbValue,
+    {
+        Ok(self.modify(table_def, key, edit)?.is_some())
+    }
+
+    /// Applies `edit` to the given entry, replacing the old value.
+    ///
+    /// Returns the values before and after the edit, or `None` if the key isn't found in the given table.
+    pub fn replace_with<K, V>(
+        &mut self,
+        table_def: TableDefinition<Bincode<K>, Bincode<V>>,
+        key: &K,
+        edit: impl FnOnce(&mut V),
+    ) -> Result<Option<(V, V)>, CakeError>
+    where
+        K: DbKey,
+        V: DbValue + Clone,
+    {
+        self.modify(table_def, key, |value| {
+            let old = value.clone();
+            edit(value);
+            (old, value.clone())
+        })
+    }
+
     /// Removes the given key.
     ///
     /// If it was present, its value is returned.
@@ -114,7 +339,7 @@ impl CakeDb {
         &mut self,
         table_def: TableDefinition<Bincode<K>, Bincode<V>>,
         key: &K,
-    ) -> Result<Option<V>, Box<dyn std::error::Error>>
+    ) -> Result<Option<V>, CakeError>
     where
         K: DbKey,
         V: DbValue,
@@ -126,6 +351,9 @@ impl CakeDb {
             let mut table = transaction.open_table(table_def)?;
             old_value = table.remove(key)?.map(|guard| guard.value());
         }
+        if old_value.is_some() {
+            self.record_write(&transaction, table_def.name())?;
+        }
         transaction.commit()?;
 
         Ok(old_value)
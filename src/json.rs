@@ -0,0 +1,114 @@
+use std::{
+    collections::BTreeMap,
+    io::{Read, Write},
+};
+
+use base64::{engine::general_purpose::STANDARD, Engine};
+use redb::{ReadableDatabase, TableHandle};
+use serde_derive::{Deserialize, Serialize};
+
+use crate::{
+    error::CakeError,
+    generic::{
+        copy::{replace_raw_table, stored_type_name, visit_raw_table, TableTypes},
+        internal::REVISIONS_TABLE,
+    },
+    CakeDb,
+};
+
+/// A table in a JSON dump, with its stored types and the base64 of its stored bytes.
+#[derive(Serialize, Deserialize)]
+struct JsonTable {
+    key_type: String,
+    key_width: Option<usize>,
+    value_type: String,
+    value_width: Option<usize>,
+    entries: Vec<JsonEntry>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct JsonEntry {
+    key: String,
+    value: String,
+}
+
+impl CakeDb {
+    /// Writes every table of the database to `writer` as a JSON object keyed by table name.
+    ///
+    /// Tables are read by name, so their types don't need to be known: each one records the type
+    /// names it was created with and holds its entries in key order, as `{ "key", "value" }` objects
+    /// with the stored bytes in base64. Multimap tables aren't included.
+    /// Load the output with [`import_database_json`](Self::import_database_json).
+    pub fn export_database_json(&self, writer: impl Write) -> Result<(), CakeError> {
+        let read = self.inner.begin_read()?;
+
+        let mut tables = BTreeMap::new();
+        for handle in read.list_tables()? {
+            if handle.name() == REVISIONS_TABLE.name() {
+                continue;
+            }
+
+            let mut entries = Vec::new();
+            let types = visit_raw_table(&read, handle.name(), |key, value| {
+                entries.push(JsonEntry {
+                    key: STANDARD.encode(key),
+                    value: STANDARD.encode(value),
+                });
+                Ok(())
+            })?;
+            let Some(types) = types else { continue };
+
+            tables.insert(
+                handle.name().to_string(),
+                JsonTable {
+                    key_type: types.key.name().to_string(),
+                    key_width: types.key_width,
+                    value_type: types.value.name().to_string(),
+                    value_width: types.value_width,
+                    entries,
+                },
+            );
+        }
+        serde_json::to_writer_pretty(writer, &tables)?;
+
+        Ok(())
+    }
+
+    /// Loads the tables from a dump written by [`export_database_json`](Self::export_database_json).
+    ///
+    /// Each table in the dump replaces the table of the same name, keeping the types it was
+    /// exported with; tables missing from the dump are left untouched. Either all tables are
+    /// loaded or none are. Entries are inserted in the order they appear, so they must stay in the
+    /// order they were exported in.
+    pub fn import_database_json(&mut self, reader: impl Read) -> Result<(), CakeError> {
+        let tables: BTreeMap<String, JsonTable> = serde_json::from_reader(reader)?;
+
+        let transaction = self.inner.begin_write()?;
+        for (name, table) in &tables {
+            if name == REVISIONS_TABLE.name() {
+                return Err(CakeError::InvalidArgument(format!(
+                    "table name {name:?} is reserved"
+                )));
+            }
+
+            let types = TableTypes {
+                key: stored_type_name(&table.key_type),
+                key_width: table.key_width,
+                value: stored_type_name(&table.value_type),
+                value_width: table.value_width,
+            };
+            let pairs = table
+                .entries
+                .iter()
+                .map(|entry| Ok((STANDARD.decode(&entry.key)?, STANDARD.decode(&entry.value)?)))
+                .collect::<Result<Vec<_>, CakeError>>()?;
+            let pairs = pairs.iter().map(|(k, v)| (k.as_slice(), v.as_slice()));
+
+            replace_raw_table(&transaction, name, &types, pairs)?;
+            self.record_write(&transaction, name)?;
+        }
+        transaction.commit()?;
+
+        Ok(())
+    }
+}
@@ -1,5 +1,8 @@
 pub mod bincode_wrapper;
+pub mod error;
 pub mod generic;
+#[cfg(feature = "json")]
+pub mod json;
 pub mod prelude;
 pub mod save;
 mod test;
@@ -9,7 +12,15 @@ use std::{
     path::{Path, PathBuf},
 };
 
-use save::CakeSavepoint;
+use bincode_wrapper::Bincode;
+use error::CakeError;
+use generic::{
+    batch_writes::TableBudget,
+    copy::write_copy,
+    traits::{DbKey, DbValue},
+};
+use redb::{ReadableDatabase, TableDefinition, TableHandle, Value};
+use save::{CakeSavepoint, SavepointStrategy};
 use tempfile::NamedTempFile;
 
 /// Represents a high-level database encapsulation that handles interactions with the underlying storage.
@@ -38,7 +49,7 @@ use tempfile::NamedTempFile;
 /// const TABLE: TableDefinition<Bincode<u32>, Bincode<TestStruct>> =
 ///         TableDefinition::new("test_table");
 ///
-/// fn main() -> Result<(), Box<dyn std::error::Error>> {
+/// fn main() -> Result<(), CakeError> {
 ///     // In production, use the `new` method instead of `new_temp`.
 ///     let mut db = CakeDb::new_temp()?;
 ///
@@ -73,6 +84,11 @@ use tempfile::NamedTempFile;
 pub struct CakeDb {
     inner: redb::Database,
     savepoints: BTreeMap<usize, CakeSavepoint>,
+    savepoint_strategy: SavepointStrategy,
+    auto_savepoint_on_destructive: bool,
+    table_types: BTreeMap<String, (String, String)>,
+    table_budgets: BTreeMap<String, TableBudget>,
+    path: PathBuf,
     tempfile_path: Option<PathBuf>,
 }
 
@@ -84,12 +100,67 @@ impl CakeDb {
     /// [`data_local_path`](crate::data_local_path).
     pub fn new(path: impl AsRef<Path>) -> Result<Self, redb::DatabaseError> {
         Ok(Self {
-            inner: redb::Database::create(path)?,
+            inner: redb::Database::create(&path)?,
             savepoints: BTreeMap::new(),
+            savepoint_strategy: SavepointStrategy::default(),
+            auto_savepoint_on_destructive: false,
+            table_types: BTreeMap::new(),
+            table_budgets: BTreeMap::new(),
+            path: path.as_ref().to_path_buf(),
             tempfile_path: None,
         })
     }
 
+    /// Opens the database at `path`, creating it and running `seed` if it didn't exist yet.
+    ///
+    /// Use this to populate a brand new database with default tables or rows.
+    /// If `seed` fails, the newly created file is removed so the next call seeds it again.
+    pub fn open_or_init(
+        path: impl AsRef<Path>,
+        seed: impl FnOnce(&mut CakeDb) -> Result<(), CakeError>,
+    ) -> Result<Self, CakeError> {
+        let path = path.as_ref();
+        let is_new = !path.try_exists()?;
+
+        let mut db = Self::new(path)?;
+        if is_new {
+            if let Err(e) = seed(&mut db) {
+                drop(db);
+                std::fs::remove_file(path)?;
+                return Err(e);
+            }
+        }
+
+        Ok(db)
+    }
+
+    /// Opens the database at `path` and compacts it fully before returning it.
+    ///
+    /// Savepoints only live as long as the handle that took them, so a freshly opened database
+    /// has none that could block compaction.
+    pub fn open_compacted(path: impl AsRef<Path>) -> Result<Self, CakeError> {
+        let mut db = Self::new(path)?;
+        db.compact_fully()?;
+
+        Ok(db)
+    }
+
+    /// Creates a copy of this database at `path` and opens it.
+    ///
+    /// The copy is written table by table from a single read snapshot, so it reflects one
+    /// consistent state even while other handles commit, and holds no free space.
+    /// It contains every table and multimap table, and is fully independent of `self`.
+    /// Registered table types carry over, but savepoints don't.
+    /// Returns an error if `path` already exists.
+    pub fn clone_to(&self, path: impl AsRef<Path>) -> Result<CakeDb, CakeError> {
+        write_copy(&self.inner.begin_read()?, path.as_ref(), false)?;
+
+        let mut clone = Self::new(path)?;
+        clone.table_types = self.table_types.clone();
+
+        Ok(clone)
+    }
+
     /// Initializes a fresh database in a tempfile.
     pub fn new_temp() -> Result<Self, redb::DatabaseError> {
         let path = NamedTempFile::with_suffix(".redb")
@@ -100,10 +171,33 @@ impl CakeDb {
         Ok(Self {
             inner: redb::Database::create(&path)?,
             savepoints: BTreeMap::new(),
+            savepoint_strategy: SavepointStrategy::default(),
+            auto_savepoint_on_destructive: false,
+            table_types: BTreeMap::new(),
+            table_budgets: BTreeMap::new(),
+            path: path.clone(),
             tempfile_path: Some(path),
         })
     }
 
+    /// Closes the database and returns the path of the file backing it.
+    ///
+    /// Savepoints are dropped first and the file is closed by the time this returns, so it can
+    /// be safely moved, e.g. with [`std::fs::rename`]. Open it again at its new path with
+    /// [`new`](Self::new).
+    pub fn close(self) -> Result<PathBuf, CakeError> {
+        let Self {
+            inner,
+            savepoints,
+            path,
+            ..
+        } = self;
+        drop(savepoints);
+        drop(inner);
+
+        Ok(path)
+    }
+
     /// Provides a reference to the inner `Database` struct. Use this if you need finer control.
     pub fn database(&self) -> &redb::Database {
         &self.inner
@@ -114,14 +208,138 @@ impl CakeDb {
         &mut self.inner
     }
 
+    /// Records the key and value types of the given table.
+    ///
+    /// Returns an error if a definition with the same name but different types was already
+    /// registered, so mismatched definitions surface at startup rather than on first use.
+    pub fn register_table<K, V>(
+        &mut self,
+        table_def: TableDefinition<Bincode<K>, Bincode<V>>,
+    ) -> Result<(), CakeError>
+    where
+        K: DbKey,
+        V: DbValue,
+    {
+        let types = (
+            Bincode::<K>::type_name().name().to_string(),
+            Bincode::<V>::type_name().name().to_string(),
+        );
+
+        match self.table_types.get(table_def.name()) {
+            Some(registered) if *registered != types => Err(CakeError::TableTypeMismatch {
+                table: table_def.name().to_string(),
+                registered: registered.clone(),
+                found: types,
+            }),
+            Some(_) => Ok(()),
+            None => {
+                self.table_types.insert(table_def.name().to_string(), types);
+                Ok(())
+            }
+        }
+    }
+
     /// Compacts the database file.
     ///
-    /// Returns `true` if compaction was performed, and `false` if no further compaction was possible.
+    /// Returns `true` if compaction was performed, and `false` if the file was already compact.
     ///
-    /// If you get an error due to a transaction in progress, it's probably because you have savepoints active.
-    /// Clear them and try again.
-    pub fn compact(&mut self) -> Result<bool, redb::CompactionError> {
-        self.inner.compact()
+    /// Compaction can't run while savepoints or transactions are active; the error then says which
+    /// one is blocking it, e.g. "3 active savepoints; call clear_savepoints".
+    pub fn compact(&mut self) -> Result<bool, CakeError> {
+        self.inner.compact().map_err(|e| self.compaction_error(e))
+    }
+
+    /// Compacts the database file if nothing prevents it, like [`compact`](Self::compact).
+    ///
+    /// Instead of returning an error when savepoints or transactions block compaction, this reports
+    /// that it was skipped, so a background task can call it periodically and just log the skips.
+    pub fn try_compact(&mut self) -> Result<CompactOutcome, CakeError> {
+        if self.has_savepoints() {
+            return Ok(CompactOutcome::SkippedSavepointsActive);
+        }
+
+        match self.inner.compact() {
+            Ok(compacted) => Ok(CompactOutcome::Compacted(compacted)),
+            Err(redb::CompactionError::Storage(e)) => Err(e.into()),
+            Err(_) => Ok(CompactOutcome::SkippedInUse),
+        }
+    }
+
+    /// Compacts the database file until no further compaction is possible.
+    ///
+    /// Returns the number of passes that compacted something, stopping after
+    /// [`MAX_COMPACTION_PASSES`] in any case.
+    ///
+    /// Like [`compact`](Self::compact), this fails if there are savepoints active.
+    pub fn compact_fully(&mut self) -> Result<u32, CakeError> {
+        let mut passes = 0;
+        while passes < MAX_COMPACTION_PASSES && self.compact()? {
+            passes += 1;
+        }
+
+        Ok(passes)
+    }
+
+    fn compaction_error(&self, e: redb::CompactionError) -> CakeError {
+        let reason = match e {
+            redb::CompactionError::Storage(e) => return e.into(),
+            _ if self.has_savepoints() => format!(
+                "{} active savepoints; call clear_savepoints",
+                self.savepoint_count()
+            ),
+            redb::CompactionError::PersistentSavepointExists => {
+                "a persistent savepoint exists".to_string()
+            }
+            redb::CompactionError::EphemeralSavepointExists => {
+                "an ephemeral savepoint exists".to_string()
+            }
+            redb::CompactionError::TransactionInProgress => {
+                "a transaction is still in progress".to_string()
+            }
+            e => e.to_string(),
+        };
+
+        CakeError::CompactionBlocked { reason }
+    }
+
+    /// Returns storage statistics for the whole database file.
+    pub fn stats(&self) -> Result<DatabaseStats, CakeError> {
+        let transaction = self.inner.begin_write()?;
+        let stats = transaction.stats()?;
+        transaction.abort()?;
+
+        Ok(DatabaseStats {
+            stored_bytes: stats.stored_bytes(),
+            metadata_bytes: stats.metadata_bytes(),
+            fragmented_bytes: stats.fragmented_bytes(),
+        })
+    }
+
+    /// Runs a round of periodic maintenance.
+    ///
+    /// Commits an empty transaction so pages freed by earlier writes are released, then, if
+    /// `compact_threshold` is given and the [fragmentation ratio](DatabaseStats::fragmentation_ratio)
+    /// exceeds it, compacts the file with [`compact_fully`](Self::compact_fully).
+    pub fn maintain(
+        &mut self,
+        compact_threshold: Option<f64>,
+    ) -> Result<MaintenanceReport, CakeError> {
+        self.inner.begin_write()?.commit()?;
+
+        let stats_before = self.stats()?;
+        let passes = match compact_threshold {
+            Some(threshold) if stats_before.fragmentation_ratio() > threshold => {
+                self.compact_fully()?
+            }
+            _ => 0,
+        };
+
+        Ok(MaintenanceReport {
+            compacted: passes > 0,
+            passes,
+            stats_before,
+            stats_after: self.stats()?,
+        })
     }
 
     /// Returns the path to the tempfile this database is stored in.
@@ -132,6 +350,56 @@ impl CakeDb {
     }
 }
 
+/// Outcome of [`CakeDb::try_compact`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CompactOutcome {
+    /// Compaction ran; `true` if it freed space, `false` if the file was already compact.
+    Compacted(bool),
+    /// Compaction was skipped because savepoints are active.
+    SkippedSavepointsActive,
+    /// Compaction was skipped because the database is in use, e.g. by an open transaction.
+    SkippedInUse,
+}
+
+/// Storage statistics for a database file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DatabaseStats {
+    /// Number of bytes consumed by keys and values.
+    pub stored_bytes: u64,
+    /// Number of bytes consumed by internal metadata.
+    pub metadata_bytes: u64,
+    /// Number of bytes consumed by fragmentation.
+    pub fragmented_bytes: u64,
+}
+
+impl DatabaseStats {
+    /// Returns the fraction of used bytes lost to fragmentation, between `0.0` and `1.0`.
+    pub fn fragmentation_ratio(&self) -> f64 {
+        let total = self.stored_bytes + self.metadata_bytes + self.fragmented_bytes;
+        if total == 0 {
+            return 0.0;
+        }
+
+        self.fragmented_bytes as f64 / total as f64
+    }
+}
+
+/// Outcome of [`CakeDb::maintain`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MaintenanceReport {
+    /// Whether any compaction pass freed space.
+    pub compacted: bool,
+    /// Number of compaction passes that compacted something.
+    pub passes: u32,
+    /// Statistics before compacting.
+    pub stats_before: DatabaseStats,
+    /// Statistics after compacting.
+    pub stats_after: DatabaseStats,
+}
+
+/// Maximum number of passes [`CakeDb::compact_fully`] performs.
+pub const MAX_COMPACTION_PASSES: u32 = 64;
+
 /// Returns the path to your computer's local data directory.
 ///
 /// | Platform | Value                                                             | Example                                               |
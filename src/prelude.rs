@@ -1,4 +1,4 @@
-pub use crate::{bincode_wrapper::Bincode, CakeDb};
+pub use crate::{bincode_wrapper::Bincode, error::CakeError, CakeDb};
 pub use bincode::{Decode, Encode};
 pub use redb::TableDefinition;
 pub use serde_derive::{Deserialize, Serialize};
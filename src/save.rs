@@ -1,9 +1,35 @@
-use std::collections::BTreeMap;
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fs::File,
+    hash::{DefaultHasher, Hash, Hasher},
+    io::{Read, Write},
+    num::NonZeroUsize,
+    path::{Path, PathBuf},
+};
 
-use redb::Savepoint;
+use bincode::{config, Decode, Encode};
+use redb::{
+    backends::InMemoryBackend, MultimapTableHandle, ReadTransaction, ReadableDatabase,
+    ReadableTable, Savepoint, TableDefinition, TableError, TableHandle,
+};
 use time::UtcDateTime;
 
-use crate::CakeDb;
+use crate::{
+    bincode_wrapper::{Bincode, RawBincode, MAX_DECODE_LIMIT},
+    error::CakeError,
+    generic::{
+        copy::{
+            replace_raw_table, staging_file, stored_type_name, visit_raw_multimap_table,
+            visit_raw_table, write_copy, TableTypes,
+        },
+        internal::{advance_revisions, open_existing_table, read_revisions, REVISIONS_TABLE},
+        traits::{DbKey, DbValue},
+    },
+    CakeDb,
+};
+
+/// Encoded key-value pairs, as stored in a table snapshot blob.
+type RawPairs = Vec<(Vec<u8>, Vec<u8>)>;
 
 /// Metadata for a savepoint stored in memory.
 pub struct CakeSavepoint {
@@ -11,6 +37,26 @@ pub struct CakeSavepoint {
     pub savepoint: Savepoint,
     /// When the savepoint was created.
     pub creation_time: UtcDateTime,
+    /// A read transaction pinned to the state at the savepoint, to read it without restoring.
+    ///
+    /// While it's open, `redb` can't free any page that was live at the savepoint, so every
+    /// savepoint kept around holds on to the space of the data overwritten since it was taken,
+    /// and compaction is blocked. Drop savepoints you no longer need, or bound how many are kept
+    /// with [`SavepointStrategy`].
+    pub snapshot: ReadTransaction,
+}
+
+/// How many savepoints a [`CakeDb`] keeps around.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum SavepointStrategy {
+    /// Keep every savepoint until it's loaded over or cleared.
+    #[default]
+    Unbounded,
+    /// Keep at most this many savepoints, evicting the oldest when a new one is created.
+    ///
+    /// At least one is always kept, so the savepoint just created is never evicted.
+    /// Keys keep increasing monotonically, so evicted keys are never reused.
+    BoundedEvictOldest(NonZeroUsize),
 }
 
 impl CakeDb {
@@ -18,10 +64,11 @@ impl CakeDb {
     ///
     /// The savepoint is stored in memory inside the struct itself, not in the database.
     /// These savepoints are ephemeral and become invalid if the [`CakeDb`] instance is dropped.
-    pub fn savepoint(&mut self) -> Result<usize, Box<dyn std::error::Error>> {
+    pub fn savepoint(&mut self) -> Result<usize, CakeError> {
         let write = self.inner.begin_write()?;
         let savepoint = write.ephemeral_savepoint()?;
         write.commit()?;
+        let snapshot = self.inner.begin_read()?;
 
         let key: usize;
         if let Some((max_key, _)) = self.savepoints.last_key_value() {
@@ -35,28 +82,427 @@ impl CakeDb {
             CakeSavepoint {
                 savepoint,
                 creation_time: UtcDateTime::now(),
+                snapshot,
             },
         );
+        self.evict_savepoints();
 
         Ok(key)
     }
 
+    /// Sets how many savepoints are kept, evicting the oldest ones if they no longer fit.
+    pub fn set_savepoint_strategy(&mut self, strategy: SavepointStrategy) {
+        self.savepoint_strategy = strategy;
+        self.evict_savepoints();
+    }
+
+    /// Returns the current savepoint strategy.
+    pub const fn savepoint_strategy(&self) -> SavepointStrategy {
+        self.savepoint_strategy
+    }
+
+    /// Sets whether the `*_safe` variants of destructive operations, like
+    /// [`clear_table_safe`](Self::clear_table_safe), take a savepoint before mutating anything.
+    ///
+    /// Disabled by default.
+    pub fn set_auto_savepoint_on_destructive(&mut self, enabled: bool) {
+        self.auto_savepoint_on_destructive = enabled;
+    }
+
+    /// Takes a savepoint if automatic savepoints are enabled, and returns its key.
+    pub(crate) fn auto_savepoint(&mut self) -> Result<Option<usize>, CakeError> {
+        if !self.auto_savepoint_on_destructive {
+            return Ok(None);
+        }
+
+        Ok(Some(self.savepoint()?))
+    }
+
+    /// Evicts the oldest savepoints until the current strategy is satisfied.
+    fn evict_savepoints(&mut self) {
+        if let SavepointStrategy::BoundedEvictOldest(limit) = self.savepoint_strategy {
+            while self.savepoints.len() > limit.get() {
+                self.savepoints.pop_first();
+            }
+        }
+    }
+
     /// Loads a savepoint from its `key`.
     ///
     /// This will invalidate any savepoints created after the savepoint being loaded.
     ///
+    /// Returns the keys of the invalidated savepoints, in ascending order.
     /// Returns an error if there's no savepoint with a matching `key`.
-    pub fn load_savepoint(&mut self, key: usize) -> Result<(), Box<dyn std::error::Error>> {
+    pub fn load_savepoint(&mut self, key: usize) -> Result<Vec<usize>, CakeError> {
         let Some(save) = self.savepoints.get(&key) else {
-            return Err(anyhow::anyhow!("failed to get specified savepoint: {key}").into());
+            return Err(CakeError::SavepointNotFound(key));
         };
 
+        let revisions = read_revisions(&self.inner.begin_read()?)?;
         let mut transaction = self.inner.begin_write()?;
         transaction.restore_savepoint(&save.savepoint)?;
+        advance_revisions(&transaction, revisions)?;
         transaction.commit()?;
 
         // After loading a savepoint, savepoints created after it are invalidated; remove them.
-        self.savepoints.retain(|k, _| k <= &key);
+        let invalidated = self.savepoints.split_off(&(key + 1));
+
+        Ok(invalidated.into_keys().collect())
+    }
+
+    /// Returns `true` if every table currently holds the same contents as at the savepoint `key`.
+    ///
+    /// The savepoint is read from its snapshot, so nothing is restored and later savepoints stay valid.
+    /// Use this to skip a pointless [`load_savepoint`](Self::load_savepoint). All tables and
+    /// multimap tables are compared by name, by their stored types and bytes, so their definitions
+    /// don't need to be known. A missing table counts as equal to an empty one.
+    /// Returns an error if there's no savepoint with a matching `key`.
+    pub fn savepoint_matches_current(&self, key: usize) -> Result<bool, CakeError> {
+        let Some(save) = self.savepoints.get(&key) else {
+            return Err(CakeError::SavepointNotFound(key));
+        };
+        let current = self.inner.begin_read()?;
+
+        let mut tables = BTreeSet::new();
+        let mut multimap_tables = BTreeSet::new();
+        for read in [&save.snapshot, &current] {
+            tables.extend(read.list_tables()?.map(|handle| handle.name().to_string()));
+            multimap_tables.extend(
+                read.list_multimap_tables()?
+                    .map(|handle| handle.name().to_string()),
+            );
+        }
+        tables.remove(REVISIONS_TABLE.name());
+
+        for name in &tables {
+            if checksum_raw_table(&save.snapshot, name, false)?
+                != checksum_raw_table(&current, name, false)?
+            {
+                return Ok(false);
+            }
+        }
+        for name in &multimap_tables {
+            if checksum_raw_table(&save.snapshot, name, true)?
+                != checksum_raw_table(&current, name, true)?
+            {
+                return Ok(false);
+            }
+        }
+
+        Ok(true)
+    }
+
+    /// Returns the keys of the given table that were added, removed, or modified since the savepoint `key`, in ascending order.
+    ///
+    /// Like [`savepoint_matches_current`](Self::savepoint_matches_current), this reads the savepoint's
+    /// snapshot, so nothing is restored. Values are compared by their stored bytes.
+    /// Returns an error if there's no savepoint with a matching `key`.
+    pub fn changed_keys_since_savepoint<K, V>(
+        &self,
+        key: usize,
+        table_def: TableDefinition<Bincode<K>, Bincode<V>>,
+    ) -> Result<Vec<K>, CakeError>
+    where
+        K: DbKey,
+        V: DbValue,
+    {
+        let Some(save) = self.savepoints.get(&key) else {
+            return Err(CakeError::SavepointNotFound(key));
+        };
+
+        let decode = |pairs: RawPairs| {
+            pairs
+                .into_iter()
+                .map(|(k, v)| Ok((Bincode::<K>::try_from_bytes(&k)?, v)))
+                .collect::<Result<BTreeMap<K, Vec<u8>>, CakeError>>()
+        };
+        let mut before = decode(read_raw_pairs(&save.snapshot, table_def)?)?;
+        let after = decode(read_raw_pairs(&self.inner.begin_read()?, table_def)?)?;
+
+        let mut changed = BTreeSet::new();
+        for (k, value) in after {
+            if before.remove(&k).as_ref() != Some(&value) {
+                changed.insert(k);
+            }
+        }
+        changed.extend(before.into_keys());
+
+        Ok(changed.into_iter().collect())
+    }
+
+    /// Snapshots the contents of a single table into memory.
+    ///
+    /// This isn't a `redb` savepoint: the whole table is copied into the returned map, so
+    /// the memory cost grows with the size of the table. Pass the snapshot to
+    /// [`restore_table_savepoint`](Self::restore_table_savepoint) to roll back only this table.
+    pub fn table_savepoint<K, V>(
+        &mut self,
+        table_def: TableDefinition<Bincode<K>, Bincode<V>>,
+    ) -> Result<BTreeMap<K, V>, CakeError>
+    where
+        K: DbKey,
+        V: DbValue,
+    {
+        self.table(table_def)
+    }
+
+    /// Restores a table to a snapshot taken with [`table_savepoint`](Self::table_savepoint).
+    ///
+    /// Only the given table is cleared and reloaded; every other table is left untouched.
+    pub fn restore_table_savepoint<K, V>(
+        &mut self,
+        table_def: TableDefinition<Bincode<K>, Bincode<V>>,
+        snapshot: BTreeMap<K, V>,
+    ) -> Result<(), CakeError>
+    where
+        K: DbKey,
+        V: DbValue,
+    {
+        self.load_from_map(table_def, snapshot)
+    }
+
+    /// Snapshots the raw contents of a single table into an in-memory blob.
+    ///
+    /// Keys and values are copied as stored, without decoding them. Pass the blob to
+    /// [`restore_table_bytes`](Self::restore_table_bytes) to roll back only this table.
+    pub fn snapshot_table_bytes<K, V>(
+        &self,
+        table_def: TableDefinition<Bincode<K>, Bincode<V>>,
+    ) -> Result<Vec<u8>, CakeError>
+    where
+        K: DbKey,
+        V: DbValue,
+    {
+        let pairs = read_raw_pairs(&self.inner.begin_read()?, table_def)?;
+
+        Ok(bincode::encode_to_vec(pairs, config::standard())?)
+    }
+
+    /// Restores a table from a blob taken with [`snapshot_table_bytes`](Self::snapshot_table_bytes).
+    ///
+    /// Only the given table is cleared and reloaded; every other table is left untouched.
+    pub fn restore_table_bytes<K, V>(
+        &mut self,
+        table_def: TableDefinition<Bincode<K>, Bincode<V>>,
+        blob: &[u8],
+    ) -> Result<(), CakeError>
+    where
+        K: DbKey,
+        V: DbValue,
+    {
+        let (pairs, _): (RawPairs, usize) =
+            bincode::decode_from_slice(blob, config::standard().with_limit::<MAX_DECODE_LIMIT>())?;
+
+        self.write_raw_pairs(table_def, pairs)
+    }
+
+    /// Writes the raw contents of the tables named in `tables`, as of the savepoint `key`, to `writer`.
+    ///
+    /// The savepoint is read from its snapshot, so nothing is restored and later savepoints stay
+    /// valid. Tables are looked up by name, so their types don't need to be known; a table that
+    /// didn't exist at the savepoint is recorded as absent. Pass the output to
+    /// [`apply_snapshot`](Self::apply_snapshot), possibly on another database, to load that state.
+    /// Returns an error if there's no savepoint with a matching `key`.
+    pub fn export_savepoint_tables(
+        &self,
+        key: usize,
+        tables: &[&str],
+        mut writer: impl Write,
+    ) -> Result<(), CakeError> {
+        let Some(save) = self.savepoints.get(&key) else {
+            return Err(CakeError::SavepointNotFound(key));
+        };
+
+        let mut snapshots = Vec::with_capacity(tables.len());
+        for name in tables {
+            let mut pairs = RawPairs::new();
+            let types = visit_raw_table(&save.snapshot, name, |key, value| {
+                pairs.push((key.to_vec(), value.to_vec()));
+                Ok(())
+            })?;
+            snapshots.push(TableSnapshot {
+                name: name.to_string(),
+                types: types.map(|types| {
+                    (
+                        types.key.name().to_string(),
+                        types.key_width,
+                        types.value.name().to_string(),
+                        types.value_width,
+                    )
+                }),
+                pairs,
+            });
+        }
+        bincode::encode_into_std_write(snapshots, &mut writer, config::standard())?;
+
+        Ok(())
+    }
+
+    /// Loads the tables from a snapshot written by [`export_savepoint_tables`](Self::export_savepoint_tables).
+    ///
+    /// Each exported table replaces the table of the same name, keeping the types it was exported
+    /// with, and tables recorded as absent are deleted. Every other table is left untouched, and
+    /// either all tables are loaded or none are. Pairs are inserted in the order they were
+    /// exported, since their types aren't known here, so only apply unmodified snapshots.
+    pub fn apply_snapshot(&mut self, mut reader: impl Read) -> Result<(), CakeError> {
+        let snapshots: Vec<TableSnapshot> = bincode::decode_from_std_read(
+            &mut reader,
+            config::standard().with_limit::<MAX_DECODE_LIMIT>(),
+        )?;
+
+        let transaction = self.inner.begin_write()?;
+        for snapshot in &snapshots {
+            match &snapshot.types {
+                Some((key, key_width, value, value_width)) => {
+                    let types = TableTypes {
+                        key: stored_type_name(key),
+                        key_width: *key_width,
+                        value: stored_type_name(value),
+                        value_width: *value_width,
+                    };
+                    let pairs = snapshot
+                        .pairs
+                        .iter()
+                        .map(|(k, v)| (k.as_slice(), v.as_slice()));
+                    replace_raw_table(&transaction, &snapshot.name, &types, pairs)?;
+                }
+                None => {
+                    let table: TableDefinition<&[u8], &[u8]> = TableDefinition::new(&snapshot.name);
+                    transaction.delete_table(table)?;
+                }
+            }
+            self.record_write(&transaction, &snapshot.name)?;
+        }
+        transaction.commit()?;
+
+        Ok(())
+    }
+
+    /// Replaces the contents of a table with already encoded key-value pairs.
+    fn write_raw_pairs<K, V>(
+        &mut self,
+        table_def: TableDefinition<Bincode<K>, Bincode<V>>,
+        pairs: RawPairs,
+    ) -> Result<(), CakeError>
+    where
+        K: DbKey,
+        V: DbValue,
+    {
+        let raw_def: TableDefinition<RawBincode<K>, RawBincode<V>> =
+            TableDefinition::new(table_def.name());
+
+        let transaction = self.inner.begin_write()?;
+        {
+            let mut table = transaction.open_table(raw_def)?;
+            table.retain(|_, _| false)?;
+
+            for (key, value) in &pairs {
+                table.insert(key.as_slice(), value.as_slice())?;
+            }
+        }
+        self.record_write(&transaction, table_def.name())?;
+        transaction.commit()?;
+
+        Ok(())
+    }
+
+    /// Returns the value of `key` as of each stored savepoint, in ascending savepoint order.
+    ///
+    /// Savepoints are read from snapshots kept alongside them, so nothing is restored
+    /// and neither the live state nor later savepoints are affected.
+    #[allow(clippy::type_complexity)]
+    pub fn key_history<K, V>(
+        &self,
+        table_def: TableDefinition<Bincode<K>, Bincode<V>>,
+        key: &K,
+    ) -> Result<Vec<(usize, Option<V>)>, CakeError>
+    where
+        K: DbKey,
+        V: DbValue,
+    {
+        let mut history = Vec::new();
+        for (save_key, save) in &self.savepoints {
+            let value = match open_existing_table(&save.snapshot, table_def)? {
+                Some(table) => table.get(key)?.map(|g| g.value()),
+                None => None,
+            };
+            history.push((*save_key, value));
+        }
+
+        Ok(history)
+    }
+
+    /// Backs up the database to `dir/name.redb` as a compacted copy, and returns its path.
+    ///
+    /// The checkpoint is written from a single read snapshot, so it reflects one consistent
+    /// state even while other handles commit. Unlike savepoints, checkpoints are regular
+    /// database files, so they survive restarts. Overwrites any checkpoint with the same name.
+    /// Returns an error if `name` is empty, or contains a path separator or `..`.
+    pub fn create_checkpoint(
+        &self,
+        name: &str,
+        dir: impl AsRef<Path>,
+    ) -> Result<PathBuf, CakeError> {
+        let path = checkpoint_path(name, &dir)?;
+        std::fs::create_dir_all(&dir)?;
+        write_copy(&self.inner.begin_read()?, &path, true)?;
+
+        Ok(path)
+    }
+
+    /// Returns the names of the checkpoints in `dir`, in ascending order.
+    pub fn list_checkpoints(dir: impl AsRef<Path>) -> Result<Vec<String>, CakeError> {
+        let mut names = Vec::new();
+        for entry in std::fs::read_dir(dir)? {
+            let path = entry?.path();
+            if path
+                .extension()
+                .is_some_and(|ext| ext == CHECKPOINT_EXTENSION)
+            {
+                if let Some(name) = path.file_stem().and_then(|stem| stem.to_str()) {
+                    names.push(name.to_string());
+                }
+            }
+        }
+        names.sort();
+
+        Ok(names)
+    }
+
+    /// Replaces the live database with the checkpoint `name` from `dir`.
+    ///
+    /// The checkpoint is first copied next to the live file and synced, then renamed over it,
+    /// so a failed copy leaves the live database untouched.
+    /// All savepoints are cleared, since they belong to the replaced database.
+    /// Returns an error if there's no checkpoint with a matching `name`, or if `name` is invalid
+    /// as in [`create_checkpoint`](Self::create_checkpoint).
+    pub fn restore_checkpoint(
+        &mut self,
+        name: &str,
+        dir: impl AsRef<Path>,
+    ) -> Result<(), CakeError> {
+        let source = checkpoint_path(name, dir)?;
+        if !source.try_exists()? {
+            return Err(CakeError::CheckpointNotFound(name.to_string()));
+        }
+
+        let mut staged = staging_file(&self.path)?;
+        std::io::copy(&mut File::open(&source)?, staged.as_file_mut())?;
+        staged.as_file().sync_all()?;
+
+        let revisions = read_revisions(&self.inner.begin_read()?)?;
+        self.clear_savepoints();
+        // The live file must be closed before it's replaced, so hold an in-memory placeholder meanwhile.
+        let placeholder = redb::Database::builder().create_with_backend(InMemoryBackend::new())?;
+        drop(std::mem::replace(&mut self.inner, placeholder));
+
+        let renamed = staged.persist(&self.path);
+        self.inner = redb::Database::create(&self.path)?;
+        renamed.map_err(|e| e.error)?;
+
+        let transaction = self.inner.begin_write()?;
+        advance_revisions(&transaction, revisions)?;
+        transaction.commit()?;
 
         Ok(())
     }
@@ -66,8 +512,118 @@ impl CakeDb {
         &self.savepoints
     }
 
+    /// Returns the number of currently stored savepoints.
+    pub fn savepoint_count(&self) -> usize {
+        self.savepoints.len()
+    }
+
+    /// Returns `true` if any savepoints are currently stored.
+    pub fn has_savepoints(&self) -> bool {
+        !self.savepoints.is_empty()
+    }
+
     /// Frees all currently stored savepoints.
     pub fn clear_savepoints(&mut self) {
         self.savepoints.clear();
     }
+
+    /// Frees all currently stored savepoints, then commits an empty write transaction.
+    ///
+    /// Savepoints pin old versions of the data, and `redb` only reclaims them on a later commit.
+    /// Committing right away frees those pages immediately, so the file can shrink without
+    /// waiting for the next write or a [`compact`](Self::compact).
+    pub fn clear_savepoints_and_gc(&mut self) -> Result<(), CakeError> {
+        self.clear_savepoints();
+        self.inner.begin_write()?.commit()?;
+
+        Ok(())
+    }
+}
+
+/// Copies the stored bytes of every key-value pair of the given table in `read`, in key order.
+fn read_raw_pairs<K, V>(
+    read: &ReadTransaction,
+    table_def: TableDefinition<Bincode<K>, Bincode<V>>,
+) -> Result<RawPairs, CakeError>
+where
+    K: DbKey,
+    V: DbValue,
+{
+    let raw_def: TableDefinition<RawBincode<K>, RawBincode<V>> =
+        TableDefinition::new(table_def.name());
+
+    let mut pairs = RawPairs::new();
+    if let Some(table) = open_existing_table(read, raw_def)? {
+        for pair in table.iter()? {
+            let (kg, vg) = pair?;
+            pairs.push((kg.value().to_vec(), vg.value().to_vec()));
+        }
+    }
+
+    Ok(pairs)
+}
+
+/// A table as recorded by [`CakeDb::export_savepoint_tables`].
+#[derive(Encode, Decode)]
+struct TableSnapshot {
+    name: String,
+    /// Key type name, key width, value type name, and value width; `None` if the table didn't exist.
+    types: Option<(String, Option<usize>, String, Option<usize>)>,
+    pairs: RawPairs,
+}
+
+/// File extension of checkpoints created by [`CakeDb::create_checkpoint`].
+const CHECKPOINT_EXTENSION: &str = "redb";
+
+/// Returns the path of the checkpoint `name` in `dir`.
+///
+/// Returns an error if `name` is empty, or contains a path separator or `..`, since it
+/// could then point outside `dir`.
+fn checkpoint_path(name: &str, dir: impl AsRef<Path>) -> Result<PathBuf, CakeError> {
+    if name.is_empty() || name.contains(['/', '\\']) || name.contains("..") {
+        return Err(CakeError::InvalidArgument(format!(
+            "invalid checkpoint name: {name:?}"
+        )));
+    }
+
+    Ok(dir.as_ref().join(format!("{name}.{CHECKPOINT_EXTENSION}")))
+}
+
+/// Hashes the stored types and bytes of the table or multimap table `name` in `read`.
+///
+/// A missing table hashes the same as an empty one, and so does a table of the other kind.
+fn checksum_raw_table(
+    read: &ReadTransaction,
+    name: &str,
+    multimap: bool,
+) -> Result<u64, CakeError> {
+    let mut hasher = DefaultHasher::new();
+    let mut empty = true;
+    let hash_pair = |key: &[u8], value: &[u8]| {
+        empty = false;
+        key.hash(&mut hasher);
+        value.hash(&mut hasher);
+        Ok(())
+    };
+
+    let visited = if multimap {
+        visit_raw_multimap_table(read, name, hash_pair)
+    } else {
+        visit_raw_table(read, name, hash_pair)
+    };
+    let types = match visited {
+        Ok(types) => types,
+        Err(CakeError::Table(
+            TableError::TableIsMultimap(_) | TableError::TableIsNotMultimap(_),
+        )) => None,
+        Err(e) => return Err(e),
+    };
+    if let (false, Some(types)) = (empty, types) {
+        types.key.name().hash(&mut hasher);
+        types.key_width.hash(&mut hasher);
+        types.value.name().hash(&mut hasher);
+        types.value_width.hash(&mut hasher);
+    }
+
+    Ok(hasher.finish())
 }
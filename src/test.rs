@@ -1,8 +1,18 @@
 #![cfg(test)]
 
-use std::collections::{BTreeMap, BTreeSet};
+use std::{
+    cell::Cell,
+    collections::{BTreeMap, BTreeSet},
+    num::{NonZeroU64, NonZeroUsize},
+    rc::Rc,
+};
 
-use crate::prelude::*;
+use crate::{prelude::*, CompactOutcome};
+use bincode::{
+    de::Decoder,
+    enc::Encoder,
+    error::{DecodeError, EncodeError},
+};
 use redb::MultimapTableDefinition;
 
 #[derive(Serialize, Deserialize, Encode, Decode, Debug, PartialEq)]
@@ -53,12 +63,41 @@ impl ComplexRecord {
     }
 }
 
+/// Encodes normally, but panics if it's ever decoded.
+#[derive(Serialize, Deserialize, Encode, Debug)]
+struct Undecodable(u32);
+
+impl<C> Decode<C> for Undecodable {
+    fn decode<D: Decoder<Context = C>>(_: &mut D) -> Result<Self, DecodeError> {
+        panic!("value was decoded")
+    }
+}
+
+/// Decodes normally, but fails to encode when it holds `0`.
+#[derive(Serialize, Deserialize, Decode, Debug)]
+struct Unencodable(u32);
+
+impl Encode for Unencodable {
+    fn encode<E: Encoder>(&self, encoder: &mut E) -> Result<(), EncodeError> {
+        if self.0 == 0 {
+            return Err(EncodeError::Other("zero can't be encoded"));
+        }
+        self.0.encode(encoder)
+    }
+}
+
+/// Key type that deliberately doesn't implement `Clone`.
+#[derive(Serialize, Deserialize, Encode, Decode, Debug, PartialEq, Eq, PartialOrd, Ord)]
+struct NoCloneKey(u32);
+
 const TABLE: TableDefinition<Bincode<u32>, Bincode<TestStruct>> =
     TableDefinition::new("test_table");
 const COMPLEX_TABLE: TableDefinition<Bincode<u32>, Bincode<ComplexRecord>> =
     TableDefinition::new("complex_table");
 const MULTI_TABLE: MultimapTableDefinition<Bincode<String>, Bincode<ComplexRecord>> =
     MultimapTableDefinition::new("complex_multimap");
+const UNDECODABLE_TABLE: TableDefinition<Bincode<u32>, Bincode<Undecodable>> =
+    TableDefinition::new("undecodable_table");
 
 #[test]
 fn insert_and_get() -> Result<(), Box<dyn std::error::Error>> {
@@ -106,7 +145,10 @@ fn try_add_contains_update_remove() -> Result<(), Box<dyn std::error::Error>> {
     let old = db.update(COMPLEX_TABLE, &1, |v| v.name = "beta".into())?;
     assert_eq!(old.name, "alpha");
     assert_eq!(db.get(COMPLEX_TABLE, &1)?.unwrap().name, "beta");
-    assert!(db.update(COMPLEX_TABLE, &99, |_| {}).is_err());
+    assert!(matches!(
+        db.update(COMPLEX_TABLE, &99, |_| {}),
+        Err(CakeError::KeyNotFound)
+    ));
     assert!(db.remove(COMPLEX_TABLE, &1)?.is_some());
     assert!(db.remove(COMPLEX_TABLE, &1)?.is_none());
     Ok(())
@@ -208,4 +250,1814 @@ fn savepoint_clear_and_compact() -> Result<(), Box<dyn std::error::Error>> {
     Ok(())
 }
 
+#[test]
+fn load_from_map_round_trip() -> Result<(), Box<dyn std::error::Error>> {
+    let mut db = CakeDb::new_temp()?;
+    db.insert(COMPLEX_TABLE, &9, ComplexRecord::new(9, "stale", &[]))?;
+    let map: BTreeMap<u32, ComplexRecord> = (1..=3)
+        .map(|i| (i, ComplexRecord::new(i, "record", &["x"])))
+        .collect();
+    db.load_from_map(COMPLEX_TABLE, map.clone())?;
+    assert_eq!(db.table(COMPLEX_TABLE)?, map);
+    Ok(())
+}
+
+#[test]
+fn filter_map_projects_matches() -> Result<(), Box<dyn std::error::Error>> {
+    let mut db = CakeDb::new_temp()?;
+    let records = vec![
+        (1, ComplexRecord::new(1, "one", &["red"])),
+        (2, ComplexRecord::new(2, "two", &["green"])),
+        (3, ComplexRecord::new(3, "three", &["red", "blue"])),
+    ];
+    db.batch_insert(COMPLEX_TABLE, records)?;
+    let names = db.filter_map(COMPLEX_TABLE, |_, v| {
+        v.tags.contains(&"red".to_string()).then(|| v.name.clone())
+    })?;
+    assert_eq!(names, vec!["one", "three"]);
+    Ok(())
+}
+
+#[test]
+fn iter_keys_skips_values() -> Result<(), Box<dyn std::error::Error>> {
+    let mut db = CakeDb::new_temp()?;
+    db.batch_insert(UNDECODABLE_TABLE, (0..5).map(|i| (i, Undecodable(i))))?;
+    let keys = db
+        .iter_keys(UNDECODABLE_TABLE)?
+        .collect::<Result<Vec<_>, _>>()?;
+    assert_eq!(keys, vec![0, 1, 2, 3, 4]);
+    Ok(())
+}
+
+#[test]
+fn multimap_retain_prunes_values() -> Result<(), Box<dyn std::error::Error>> {
+    let mut db = CakeDb::new_temp()?;
+    let record = |i| ComplexRecord::new(i, "r", &[]);
+    let batch = vec![
+        ("k1".to_string(), (1..=4).map(record)),
+        ("k2".to_string(), (2..=3).map(record)),
+    ];
+    db.multimap_batch_insert(MULTI_TABLE, batch)?;
+    let removed = db.multimap_retain(MULTI_TABLE, |_, v| v.id >= 3)?;
+    assert_eq!(removed, 3);
+    let ids = |key: &str| -> Result<Vec<u32>, Box<dyn std::error::Error>> {
+        let values = db.multimap_get(MULTI_TABLE, &key.to_string())?;
+        Ok(values.into_iter().map(|v| v.id).collect())
+    };
+    assert_eq!(ids("k1")?, vec![3, 4]);
+    assert_eq!(ids("k2")?, vec![3]);
+    Ok(())
+}
+
+#[test]
+fn table_savepoint_restores_one_table() -> Result<(), Box<dyn std::error::Error>> {
+    let mut db = CakeDb::new_temp()?;
+    db.insert(TABLE, &1, TestStruct::new(1, "one"))?;
+    db.insert(COMPLEX_TABLE, &1, ComplexRecord::new(1, "one", &[]))?;
+    let snapshot = db.table_savepoint(TABLE)?;
+    db.insert(TABLE, &2, TestStruct::new(2, "two"))?;
+    db.insert(COMPLEX_TABLE, &2, ComplexRecord::new(2, "two", &[]))?;
+    db.restore_table_savepoint(TABLE, snapshot)?;
+    assert_eq!(db.table(TABLE)?.len(), 1);
+    assert_eq!(db.table(COMPLEX_TABLE)?.len(), 2);
+    Ok(())
+}
+
+#[test]
+fn stateful_predicates() -> Result<(), Box<dyn std::error::Error>> {
+    let mut db = CakeDb::new_temp()?;
+    db.batch_insert(TABLE, (1..=5).map(|i| (i, TestStruct::new(i, "n"))))?;
+    let mut seen = Vec::new();
+    let odd = db.filter(TABLE, |k, v| {
+        seen.push(*k);
+        v.a % 2 == 1
+    })?;
+    assert_eq!(odd.len(), 3);
+    assert_eq!(seen, vec![1, 2, 3, 4, 5]);
+    let mut examined = 0;
+    assert_eq!(
+        db.count_matches(TABLE, |_, v| {
+            examined += 1;
+            v.a > 3
+        })?,
+        2
+    );
+    assert_eq!(examined, 5);
+    Ok(())
+}
+
+#[test]
+fn drain_iter_empties_table() -> Result<(), Box<dyn std::error::Error>> {
+    let mut db = CakeDb::new_temp()?;
+    db.batch_insert(TABLE, (1..=5).map(|i| (i, TestStruct::new(i, "n"))))?;
+    let first_two = db
+        .drain_iter(TABLE)?
+        .take(2)
+        .collect::<Result<Vec<_>, _>>()?;
+    assert_eq!(first_two.len(), 2);
+    assert_eq!(db.first_key(TABLE)?, Some(3));
+    let rest = db.drain_iter(TABLE)?.collect::<Result<Vec<_>, _>>()?;
+    let keys = rest.iter().map(|(k, _)| *k).collect::<Vec<_>>();
+    assert_eq!(keys, vec![3, 4, 5]);
+    assert_eq!(rest[0].1, TestStruct::new(3, "n"));
+    assert!(db.table(TABLE)?.is_empty());
+    Ok(())
+}
+
+#[test]
+fn open_or_init_seeds_once() -> Result<(), Box<dyn std::error::Error>> {
+    let dir = tempfile::tempdir()?;
+    let path = dir.path().join("seeded.redb");
+    let mut seeds = 0;
+    for _ in 0..2 {
+        let db = CakeDb::open_or_init(&path, |db| {
+            seeds += 1;
+            db.insert(TABLE, &1, TestStruct::new(1, "default"))?;
+            Ok(())
+        })?;
+        assert!(db.contains_key(TABLE, &1)?);
+    }
+    assert_eq!(seeds, 1);
+    Ok(())
+}
+
+#[test]
+fn prefix_counts_groups_keys() -> Result<(), Box<dyn std::error::Error>> {
+    const NAMED: TableDefinition<Bincode<String>, Bincode<u32>> =
+        TableDefinition::new("named_table");
+    let mut db = CakeDb::new_temp()?;
+    let keys = ["user:1", "user:2", "user:3", "group:1", "item:1"];
+    db.batch_insert(NAMED, keys.iter().map(|k| (k.to_string(), 0)))?;
+    let counts = db.prefix_counts(NAMED, &["user:", "group:", "none:"])?;
+    assert_eq!(counts["user:"], 3);
+    assert_eq!(counts["group:"], 1);
+    assert_eq!(counts["none:"], 0);
+    Ok(())
+}
+
+#[test]
+fn get_or_default_on_missing_key() -> Result<(), Box<dyn std::error::Error>> {
+    const COUNTERS: TableDefinition<Bincode<String>, Bincode<u64>> =
+        TableDefinition::new("counters");
+    let mut db = CakeDb::new_temp()?;
+    db.insert(COUNTERS, &"hits".to_string(), 7)?;
+    assert_eq!(db.get_or_default(COUNTERS, &"hits".to_string())?, 7);
+    assert_eq!(db.get_or_default(COUNTERS, &"misses".to_string())?, 0);
+    assert!(!db.contains_key(COUNTERS, &"misses".to_string())?);
+    Ok(())
+}
+
+#[test]
+fn remove_where_returns_removed() -> Result<(), Box<dyn std::error::Error>> {
+    let mut db = CakeDb::new_temp()?;
+    db.batch_insert(TABLE, (1..=5).map(|i| (i, TestStruct::new(i, "n"))))?;
+    let removed = db.remove_where(TABLE, |k, _| k % 2 == 0)?;
+    assert_eq!(removed.keys().copied().collect::<Vec<_>>(), vec![2, 4]);
+    assert_eq!(removed[&2], TestStruct::new(2, "n"));
+    let remaining = db.table(TABLE)?;
+    assert_eq!(remaining.keys().copied().collect::<Vec<_>>(), vec![1, 3, 5]);
+    Ok(())
+}
+
+#[test]
+fn multimap_page_walks_keys() -> Result<(), Box<dyn std::error::Error>> {
+    let mut db = CakeDb::new_temp()?;
+    let batch = ["a", "b", "c", "d", "e"]
+        .iter()
+        .map(|k| (k.to_string(), vec![ComplexRecord::new(1, k, &[])]));
+    db.multimap_batch_insert(MULTI_TABLE, batch)?;
+    let mut pages = Vec::new();
+    let mut after = None;
+    loop {
+        let page = db.multimap_page(MULTI_TABLE, after.as_ref(), 2)?;
+        let Some((last, _)) = page.last() else {
+            break;
+        };
+        after = Some(last.clone());
+        pages.push(page.into_iter().map(|(k, _)| k).collect::<Vec<_>>());
+    }
+    assert_eq!(pages, vec![vec!["a", "b"], vec!["c", "d"], vec!["e"]]);
+    Ok(())
+}
+
+#[test]
+fn replace_table_swaps_contents() -> Result<(), Box<dyn std::error::Error>> {
+    let mut db = CakeDb::new_temp()?;
+    db.batch_insert(TABLE, (1..=3).map(|i| (i, TestStruct::new(i, "old"))))?;
+    let new_data = vec![
+        (3, TestStruct::new(3, "new")),
+        (7, TestStruct::new(7, "new")),
+    ];
+    assert_eq!(db.replace_table(TABLE, new_data)?, 2);
+    let table = db.table(TABLE)?;
+    assert_eq!(table.keys().copied().collect::<Vec<_>>(), vec![3, 7]);
+    assert!(table.values().all(|v| v.b == "new"));
+    Ok(())
+}
+
+#[test]
+fn rename_and_copy_multimap_tables() -> Result<(), Box<dyn std::error::Error>> {
+    const RENAMED: MultimapTableDefinition<Bincode<String>, Bincode<ComplexRecord>> =
+        MultimapTableDefinition::new("renamed_multimap");
+    const COPIED: MultimapTableDefinition<Bincode<String>, Bincode<ComplexRecord>> =
+        MultimapTableDefinition::new("copied_multimap");
+    let mut db = CakeDb::new_temp()?;
+    let batch = vec![
+        ("k1".to_string(), vec![ComplexRecord::new(1, "one", &[])]),
+        (
+            "k2".to_string(),
+            vec![
+                ComplexRecord::new(2, "two", &[]),
+                ComplexRecord::new(3, "three", &[]),
+            ],
+        ),
+    ];
+    db.multimap_batch_insert(MULTI_TABLE, batch)?;
+    assert_eq!(db.copy_multimap_table(MULTI_TABLE, COPIED)?, 3);
+    assert_eq!(db.rename_multimap_table(MULTI_TABLE, RENAMED)?, 3);
+    let tables = db.list_multimap_tables()?;
+    assert!(!tables.contains(&"complex_multimap".to_string()));
+    assert!(tables.contains(&"renamed_multimap".to_string()));
+    assert_eq!(db.multimap_table(RENAMED)?, db.multimap_table(COPIED)?);
+    assert_eq!(db.multimap_get(RENAMED, &"k2".to_string())?.len(), 2);
+    Ok(())
+}
+
+#[test]
+fn get_into_reuses_buffer() -> Result<(), Box<dyn std::error::Error>> {
+    let mut db = CakeDb::new_temp()?;
+    db.batch_insert(TABLE, (1..=3).map(|i| (i, TestStruct::new(i, "n"))))?;
+    let mut buf = None;
+    let mut sum = 0;
+    for key in 1..=3 {
+        assert!(db.get_into(TABLE, &key, &mut buf)?);
+        sum += buf.as_ref().unwrap().a;
+    }
+    assert_eq!(sum, 6);
+    assert!(!db.get_into(TABLE, &4, &mut buf)?);
+    assert_eq!(buf.unwrap().a, 3);
+    Ok(())
+}
+
+#[test]
+fn load_savepoint_reports_invalidated() -> Result<(), Box<dyn std::error::Error>> {
+    let mut db = CakeDb::new_temp()?;
+    let first = db.savepoint()?;
+    db.savepoint()?;
+    db.savepoint()?;
+    assert_eq!(db.load_savepoint(first)?, vec![1, 2]);
+    assert_eq!(db.savepoints().keys().copied().collect::<Vec<_>>(), vec![0]);
+    assert!(db.load_savepoint(first)?.is_empty());
+    Ok(())
+}
+
+#[test]
+fn find_duplicate_values_groups_keys() -> Result<(), Box<dyn std::error::Error>> {
+    let mut db = CakeDb::new_temp()?;
+    let data = vec![
+        (1, TestStruct::new(1, "same")),
+        (2, TestStruct::new(2, "unique")),
+        (3, TestStruct::new(1, "same")),
+    ];
+    db.batch_insert(TABLE, data)?;
+    let duplicates = db.find_duplicate_values(TABLE)?;
+    assert_eq!(duplicates.len(), 1);
+    assert_eq!(duplicates.values().next().unwrap(), &vec![1, 3]);
+    Ok(())
+}
+
+#[test]
+fn read_transaction_is_one_snapshot() -> Result<(), Box<dyn std::error::Error>> {
+    use redb::ReadableTableMetadata;
+
+    let mut db = CakeDb::new_temp()?;
+    db.insert(TABLE, &1, TestStruct::new(1, "one"))?;
+    db.insert(COMPLEX_TABLE, &1, ComplexRecord::new(1, "one", &[]))?;
+    let txn = db.read_transaction()?;
+    db.insert(TABLE, &2, TestStruct::new(2, "two"))?;
+    db.insert(COMPLEX_TABLE, &2, ComplexRecord::new(2, "two", &[]))?;
+    assert_eq!(txn.open_table(TABLE)?.len()?, 1);
+    assert_eq!(txn.open_table(COMPLEX_TABLE)?.len()?, 1);
+    assert_eq!(db.read_transaction()?.open_table(TABLE)?.len()?, 2);
+    Ok(())
+}
+
+#[test]
+fn batch_insert_unique_rejects_repeats() -> Result<(), Box<dyn std::error::Error>> {
+    let mut db = CakeDb::new_temp()?;
+    let data = vec![
+        (1, TestStruct::new(1, "one")),
+        (2, TestStruct::new(2, "two")),
+        (1, TestStruct::new(3, "one again")),
+    ];
+    let err = db.batch_insert_unique(TABLE, data).unwrap_err();
+    assert!(err.to_string().contains("duplicate input key: 1"));
+    assert!(db.table(TABLE)?.is_empty());
+    db.batch_insert_unique(TABLE, vec![(1, TestStruct::new(1, "one"))])?;
+    assert!(db.contains_key(TABLE, &1)?);
+    Ok(())
+}
+
+#[test]
+fn multimap_table_stats_counts() -> Result<(), Box<dyn std::error::Error>> {
+    let mut db = CakeDb::new_temp()?;
+    let record = |i| ComplexRecord::new(i, "r", &[]);
+    let batch = vec![
+        ("k1".to_string(), (1..=3).map(record)),
+        ("k2".to_string(), (4..=5).map(record)),
+    ];
+    db.multimap_batch_insert(MULTI_TABLE, batch)?;
+    let stats = db.multimap_table_stats(MULTI_TABLE)?;
+    assert_eq!(stats.key_count, 2);
+    assert_eq!(stats.value_count, 5);
+    assert!(stats.stored_bytes > 0);
+    Ok(())
+}
+
+#[test]
+fn multimap_find_key_by_value() -> Result<(), Box<dyn std::error::Error>> {
+    let mut db = CakeDb::new_temp()?;
+    let batch = vec![
+        ("a".to_string(), vec![ComplexRecord::new(1, "one", &["x"])]),
+        ("b".to_string(), vec![ComplexRecord::new(2, "two", &["y"])]),
+        (
+            "c".to_string(),
+            vec![ComplexRecord::new(3, "three", &["y"])],
+        ),
+    ];
+    db.multimap_batch_insert(MULTI_TABLE, batch)?;
+    let has_y = |_: &String, v: &ComplexRecord| v.tags.contains(&"y".to_string());
+    assert_eq!(
+        db.multimap_find_key(MULTI_TABLE, has_y)?,
+        Some("b".to_string())
+    );
+    assert_eq!(db.multimap_find_key(MULTI_TABLE, |_, v| v.id > 5)?, None);
+    Ok(())
+}
+
+#[test]
+fn forged_length_prefix_is_rejected() {
+    use redb::Value;
+
+    // Varint marker for a `u64` length, followed by a length of 2^40 elements.
+    let mut forged = vec![253];
+    forged.extend_from_slice(&(1u64 << 40).to_le_bytes());
+    assert!(Bincode::<Vec<u8>>::try_from_bytes(&forged).is_err());
+
+    let valid = Bincode::<Vec<u8>>::as_bytes(&vec![1, 2, 3]);
+    assert_eq!(
+        Bincode::<Vec<u8>>::try_from_bytes(&valid).unwrap(),
+        vec![1, 2, 3]
+    );
+}
+
+#[test]
+fn forged_length_prefix_is_rejected_on_read() -> Result<(), Box<dyn std::error::Error>> {
+    use crate::bincode_wrapper::{decode_limit, set_decode_limit, RawBincode, MAX_DECODE_LIMIT};
+    use bincode::error::DecodeError;
+    use redb::Value;
+
+    const BYTES_TABLE: TableDefinition<Bincode<u32>, Bincode<Vec<u8>>> =
+        TableDefinition::new("bytes_table");
+    const RAW_TABLE: TableDefinition<RawBincode<u32>, RawBincode<Vec<u8>>> =
+        TableDefinition::new("bytes_table");
+
+    // A length of 64 MiB is well under any sane allocation, but above the default limit.
+    let mut forged = vec![252];
+    forged.extend_from_slice(&(64u32 << 20).to_le_bytes());
+    assert!(matches!(
+        Bincode::<Vec<u8>>::try_from_bytes(&forged),
+        Err(DecodeError::LimitExceeded)
+    ));
+
+    let mut db = CakeDb::new_temp()?;
+    db.insert(BYTES_TABLE, &1, vec![1, 2, 3])?;
+    let write = db.database().begin_write()?;
+    write
+        .open_table(RAW_TABLE)?
+        .insert(Bincode::<u32>::as_bytes(&2).as_slice(), forged.as_slice())?;
+    write.commit()?;
+    let (matches, skipped) = db.filter_lenient(BYTES_TABLE, |_, _| true)?;
+    assert_eq!(matches.into_keys().collect::<Vec<_>>(), vec![1]);
+    assert_eq!(skipped, 1);
+
+    assert!(set_decode_limit(MAX_DECODE_LIMIT + 1).is_err());
+    assert!(decode_limit() <= MAX_DECODE_LIMIT);
+    Ok(())
+}
+
+#[test]
+fn count_matches_capped_stops_early() -> Result<(), Box<dyn std::error::Error>> {
+    let mut db = CakeDb::new_temp()?;
+    db.batch_insert(TABLE, (0..100).map(|i| (i, TestStruct::new(i, "n"))))?;
+    let mut examined = 0;
+    let count = db.count_matches_capped(
+        TABLE,
+        |_, v| {
+            examined += 1;
+            v.a % 2 == 0
+        },
+        5,
+    )?;
+    assert_eq!(count, 5);
+    assert_eq!(examined, 9);
+    assert_eq!(db.count_matches_capped(TABLE, |_, v| v.a < 3, 5)?, 3);
+    Ok(())
+}
+
+#[test]
+fn update_many_applies_per_key_edits() -> Result<(), Box<dyn std::error::Error>> {
+    let mut db = CakeDb::new_temp()?;
+    db.batch_insert(TABLE, (1..=2).map(|i| (i, TestStruct::new(i, "n"))))?;
+    type Edit = Box<dyn FnOnce(&mut TestStruct)>;
+    let edits: Vec<(u32, Edit)> = vec![
+        (1, Box::new(|v| v.a += 10)),
+        (2, Box::new(|v| v.b.push_str(" (edited)"))),
+        (3, Box::new(|v| v.a = 0)),
+    ];
+    assert_eq!(db.update_many(TABLE, edits)?, 2);
+    assert_eq!(db.get(TABLE, &1)?.unwrap(), TestStruct::new(11, "n"));
+    assert_eq!(
+        db.get(TABLE, &2)?.unwrap(),
+        TestStruct::new(2, "n (edited)")
+    );
+    assert!(!db.contains_key(TABLE, &3)?);
+    Ok(())
+}
+
+#[test]
+fn emptiness_checks() -> Result<(), Box<dyn std::error::Error>> {
+    let mut db = CakeDb::new_temp()?;
+    assert!(db.database_is_empty()?);
+    assert!(db.multimap_is_empty(MULTI_TABLE)?);
+    let record = ComplexRecord::new(1, "one", &[]);
+    db.multimap_insert(MULTI_TABLE, &"k".to_string(), record.clone())?;
+    assert!(!db.multimap_is_empty(MULTI_TABLE)?);
+    assert!(!db.database_is_empty()?);
+    db.multimap_remove(MULTI_TABLE, &"k".to_string(), record)?;
+    assert!(db.multimap_is_empty(MULTI_TABLE)?);
+    assert!(db.database_is_empty()?);
+    db.insert(TABLE, &1, TestStruct::new(1, "one"))?;
+    assert!(!db.database_is_empty()?);
+    assert_eq!(db.list_tables()?, vec!["test_table"]);
+    Ok(())
+}
+
+#[test]
+fn push_assigns_sequential_keys() -> Result<(), Box<dyn std::error::Error>> {
+    const LOG: TableDefinition<Bincode<u64>, Bincode<String>> = TableDefinition::new("log");
+    let mut db = CakeDb::new_temp()?;
+    let keys = ["first", "second", "third"]
+        .into_iter()
+        .map(|entry| db.push(LOG, entry.to_string()))
+        .collect::<Result<Vec<_>, _>>()?;
+    assert_eq!(keys, vec![0, 1, 2]);
+    assert_eq!(db.get(LOG, &2)?.unwrap(), "third");
+    Ok(())
+}
+
+#[test]
+fn compact_fully_shrinks_file() -> Result<(), Box<dyn std::error::Error>> {
+    let mut db = CakeDb::new_temp()?;
+    let path = db.tempfile_path().unwrap().clone();
+    let big = "x".repeat(4096);
+    db.batch_insert(TABLE, (0..500).map(|i| (i, TestStruct::new(i, &big))))?;
+    db.remove_where(TABLE, |k, _| k % 10 != 0)?;
+    let before = std::fs::metadata(&path)?.len();
+    assert!(db.compact_fully()? >= 1);
+    assert!(std::fs::metadata(&path)?.len() <= before);
+    assert_eq!(db.table(TABLE)?.len(), 50);
+    Ok(())
+}
+
+#[test]
+fn key_history_reads_savepoints() -> Result<(), Box<dyn std::error::Error>> {
+    let mut db = CakeDb::new_temp()?;
+    db.savepoint()?;
+    db.insert(TABLE, &1, TestStruct::new(1, "first"))?;
+    db.savepoint()?;
+    db.update(TABLE, &1, |v| v.a = 2)?;
+    db.savepoint()?;
+    db.remove(TABLE, &1)?;
+    let history = db.key_history(TABLE, &1)?;
+    let values = history
+        .into_iter()
+        .map(|(key, value)| (key, value.map(|v| v.a)))
+        .collect::<Vec<_>>();
+    assert_eq!(values, vec![(0, None), (1, Some(1)), (2, Some(2))]);
+    assert!(db.get(TABLE, &1)?.is_none());
+    assert_eq!(db.savepoints().len(), 3);
+    db.load_savepoint(1)?;
+    assert_eq!(db.get(TABLE, &1)?.unwrap().a, 1);
+    Ok(())
+}
+
+#[test]
+fn filter_lenient_skips_corrupt_rows() -> Result<(), Box<dyn std::error::Error>> {
+    use crate::bincode_wrapper::RawBincode;
+    use redb::Value;
+
+    const RAW_TABLE: TableDefinition<RawBincode<u32>, RawBincode<TestStruct>> =
+        TableDefinition::new("test_table");
+    let mut db = CakeDb::new_temp()?;
+    db.batch_insert(TABLE, (1..=3).map(|i| (i, TestStruct::new(i, "n"))))?;
+    let write = db.database().begin_write()?;
+    write
+        .open_table(RAW_TABLE)?
+        .insert(Bincode::<u32>::as_bytes(&9).as_slice(), [255].as_slice())?;
+    write.commit()?;
+    let (matches, skipped) = db.filter_lenient(TABLE, |_, v| v.a > 1)?;
+    assert_eq!(matches.keys().copied().collect::<Vec<_>>(), vec![2, 3]);
+    assert_eq!(skipped, 1);
+    Ok(())
+}
+
+#[test]
+fn multimap_extend_reports_added_values() -> Result<(), Box<dyn std::error::Error>> {
+    use crate::generic::multimap_writes::MultimapExtendReport;
+
+    let mut db = CakeDb::new_temp()?;
+    let key = "tags".to_string();
+    let r1 = ComplexRecord::new(1, "one", &["a"]);
+    let r2 = ComplexRecord::new(2, "two", &["b"]);
+    let r3 = ComplexRecord::new(3, "three", &["c"]);
+    let report = db.multimap_extend(MULTI_TABLE, &key, vec![r1.clone()])?;
+    assert_eq!(
+        report,
+        MultimapExtendReport {
+            key_existed: false,
+            added: 1
+        }
+    );
+    let report = db.multimap_extend(MULTI_TABLE, &key, vec![r1, r2.clone(), r3, r2])?;
+    assert_eq!(
+        report,
+        MultimapExtendReport {
+            key_existed: true,
+            added: 2
+        }
+    );
+    assert_eq!(db.multimap_get(MULTI_TABLE, &key)?.len(), 3);
+    Ok(())
+}
+
+#[test]
+fn register_table_rejects_conflicting_types() -> Result<(), Box<dyn std::error::Error>> {
+    const CONFLICTING: TableDefinition<Bincode<u32>, Bincode<String>> =
+        TableDefinition::new("test_table");
+    let mut db = CakeDb::new_temp()?;
+    db.register_table(TABLE)?;
+    db.register_table(TABLE)?;
+    let err = db.register_table(CONFLICTING).unwrap_err();
+    assert!(err.to_string().contains("table type mismatch"));
+    Ok(())
+}
+
+#[test]
+fn modify_returns_edit_result() -> Result<(), Box<dyn std::error::Error>> {
+    let mut db = CakeDb::new_temp()?;
+    db.insert(TABLE, &1, TestStruct::new(1, "one"))?;
+    let len = db.modify(TABLE, &1, |v| {
+        v.b.push_str(" (edited)");
+        v.b.len()
+    })?;
+    assert_eq!(len, Some(12));
+    assert_eq!(db.get(TABLE, &1)?.unwrap().b, "one (edited)");
+    assert_eq!(db.modify(TABLE, &2, |v| v.a)?, None);
+    Ok(())
+}
+
+#[test]
+fn range_filter_applies_predicate_within_bounds() -> Result<(), Box<dyn std::error::Error>> {
+    let mut db = CakeDb::new_temp()?;
+    db.batch_insert(TABLE, (1..=5).map(|i| (i, TestStruct::new(i, "n"))))?;
+    let matches = db.range_filter(TABLE, 2..4, |_, v| v.a != 3)?;
+    assert_eq!(matches.keys().copied().collect::<Vec<_>>(), vec![2]);
+    Ok(())
+}
+
+#[test]
+fn first_and_last_on_missing_table() -> Result<(), Box<dyn std::error::Error>> {
+    let db = CakeDb::new_temp()?;
+    assert_eq!(db.first(TABLE)?, None);
+    assert_eq!(db.last(TABLE)?, None);
+    assert_eq!(db.first_key(TABLE)?, None);
+    assert_eq!(db.last_key(TABLE)?, None);
+    assert!(db.list_tables()?.is_empty());
+    Ok(())
+}
+
+#[test]
+fn bounded_savepoints_evict_oldest() -> Result<(), Box<dyn std::error::Error>> {
+    use crate::save::SavepointStrategy;
+
+    let limit = NonZeroUsize::new(3).ok_or("zero limit")?;
+    let mut db = CakeDb::new_temp()?;
+    db.set_savepoint_strategy(SavepointStrategy::BoundedEvictOldest(limit));
+    for _ in 0..5 {
+        db.savepoint()?;
+        assert!(db.savepoints().len() <= 3);
+    }
+    assert_eq!(
+        db.savepoints().keys().copied().collect::<Vec<_>>(),
+        vec![2, 3, 4]
+    );
+    Ok(())
+}
+
+#[test]
+fn filter_limited_truncates_scan() -> Result<(), Box<dyn std::error::Error>> {
+    let mut db = CakeDb::new_temp()?;
+    db.batch_insert(TABLE, (1..=10).map(|i| (i, TestStruct::new(i, "n"))))?;
+    let (matches, truncated) = db.filter_limited(TABLE, |_, v| v.a % 2 == 0, 5)?;
+    assert!(truncated);
+    assert_eq!(matches.keys().copied().collect::<Vec<_>>(), vec![2, 4]);
+    let (matches, truncated) = db.filter_limited(TABLE, |_, v| v.a % 2 == 0, 10)?;
+    assert!(!truncated);
+    assert_eq!(matches.len(), 5);
+    Ok(())
+}
+
+#[test]
+fn multimap_clear_key_discards_values() -> Result<(), Box<dyn std::error::Error>> {
+    let mut db = CakeDb::new_temp()?;
+    let key = "k1".to_string();
+    let values = vec![
+        ComplexRecord::new(1, "one", &["a"]),
+        ComplexRecord::new(2, "two", &["b"]),
+    ];
+    db.multimap_insert_values(MULTI_TABLE, &key, values)?;
+    assert!(db.multimap_clear_key(MULTI_TABLE, &key)?);
+    assert!(db.multimap_get(MULTI_TABLE, &key)?.is_empty());
+    assert!(!db.multimap_clear_key(MULTI_TABLE, &"absent".to_string())?);
+    Ok(())
+}
+
+#[test]
+fn replace_with_returns_old_and_new() -> Result<(), Box<dyn std::error::Error>> {
+    let mut db = CakeDb::new_temp()?;
+    let record = ComplexRecord::new(1, "one", &["a"]);
+    db.insert(COMPLEX_TABLE, &1, record.clone())?;
+    let (old, new) = db
+        .replace_with(COMPLEX_TABLE, &1, |r| r.name.push('!'))?
+        .unwrap();
+    assert_eq!(old, record);
+    assert_eq!(new.name, "one!");
+    assert_eq!(db.get(COMPLEX_TABLE, &1)?, Some(new));
+    assert!(db.replace_with(COMPLEX_TABLE, &2, |r| r.id += 1)?.is_none());
+    Ok(())
+}
+
+#[test]
+fn intersect_and_difference_keys() -> Result<(), Box<dyn std::error::Error>> {
+    let mut db = CakeDb::new_temp()?;
+    db.batch_insert(TABLE, (1..=5).map(|i| (i, TestStruct::new(i, "n"))))?;
+    db.batch_insert(
+        COMPLEX_TABLE,
+        [2, 4, 6].map(|i| (i, ComplexRecord::new(i, "r", &[]))),
+    )?;
+    assert_eq!(db.intersect_keys(TABLE, COMPLEX_TABLE)?, vec![2, 4]);
+    assert_eq!(db.difference_keys(TABLE, COMPLEX_TABLE)?, vec![1, 3, 5]);
+    assert_eq!(db.difference_keys(COMPLEX_TABLE, TABLE)?, vec![6]);
+    Ok(())
+}
 
+#[test]
+fn table_bytes_round_trip() -> Result<(), Box<dyn std::error::Error>> {
+    let mut db = CakeDb::new_temp()?;
+    db.batch_insert(TABLE, (1..=3).map(|i| (i, TestStruct::new(i, "n"))))?;
+    let blob = db.snapshot_table_bytes(TABLE)?;
+    db.remove(TABLE, &1)?;
+    db.insert(TABLE, &4, TestStruct::new(4, "four"))?;
+    db.restore_table_bytes(TABLE, &blob)?;
+    let expected: BTreeMap<_, _> = (1..=3).map(|i| (i, TestStruct::new(i, "n"))).collect();
+    assert_eq!(db.table(TABLE)?, expected);
+    Ok(())
+}
+
+#[test]
+fn table_revision_tracks_writes() -> Result<(), Box<dyn std::error::Error>> {
+    let mut db = CakeDb::new_temp()?;
+    assert_eq!(db.table_revision(TABLE)?, 0);
+    db.insert(TABLE, &1, TestStruct::new(1, "one"))?;
+    let revision = db.table_revision(TABLE)?;
+    assert!(revision > 0);
+    db.get(TABLE, &1)?;
+    db.table(TABLE)?;
+    assert_eq!(db.table_revision(TABLE)?, revision);
+    db.remove(TABLE, &9)?;
+    assert_eq!(db.table_revision(TABLE)?, revision);
+    db.insert(TABLE, &2, TestStruct::new(2, "two"))?;
+    assert!(db.table_revision(TABLE)? > revision);
+    assert_eq!(db.table_revision(COMPLEX_TABLE)?, 0);
+    assert_eq!(db.list_tables()?, vec!["test_table".to_string()]);
+    db.clear_table(TABLE)?;
+    let cleared = db.table_revision(TABLE)?;
+    db.clear_table(TABLE)?;
+    assert_eq!(db.table_revision(TABLE)?, cleared);
+    Ok(())
+}
+
+#[test]
+fn table_revision_keeps_increasing_across_restores() -> Result<(), Box<dyn std::error::Error>> {
+    let dir = tempfile::tempdir()?;
+    let mut db = CakeDb::new_temp()?;
+    let save = db.savepoint()?;
+    db.create_checkpoint("empty", dir.path())?;
+    db.insert(TABLE, &1, TestStruct::new(1, "one"))?;
+    db.insert(TABLE, &2, TestStruct::new(2, "two"))?;
+    let mut seen = db.table_revision(TABLE)?;
+
+    db.load_savepoint(save)?;
+    assert!(db.table_revision(TABLE)? > seen);
+    db.insert(TABLE, &3, TestStruct::new(3, "three"))?;
+    assert!(db.table_revision(TABLE)? > seen + 1);
+    seen = db.table_revision(TABLE)?;
+
+    db.restore_checkpoint("empty", dir.path())?;
+    assert!(db.table_revision(TABLE)? > seen);
+    db.insert(TABLE, &4, TestStruct::new(4, "four"))?;
+    assert!(db.table_revision(TABLE)? > seen + 1);
+    Ok(())
+}
+
+#[test]
+fn push_to_vec_appends_items() -> Result<(), Box<dyn std::error::Error>> {
+    const VEC_TABLE: TableDefinition<Bincode<u32>, Bincode<Vec<String>>> =
+        TableDefinition::new("vec_table");
+    let mut db = CakeDb::new_temp()?;
+    db.insert(VEC_TABLE, &1, vec!["a".to_string()])?;
+    db.push_to_vec(VEC_TABLE, &1, "b".to_string())?;
+    db.push_to_vec(VEC_TABLE, &2, "c".to_string())?;
+    assert_eq!(
+        db.get(VEC_TABLE, &1)?,
+        Some(vec!["a".to_string(), "b".to_string()])
+    );
+    assert_eq!(db.get(VEC_TABLE, &2)?, Some(vec!["c".to_string()]));
+    Ok(())
+}
+
+#[test]
+fn clear_savepoints_and_gc_shrinks_file() -> Result<(), Box<dyn std::error::Error>> {
+    // Returns the file size after clearing savepoints, and after compacting.
+    fn file_sizes(gc: bool) -> Result<(u64, u64), Box<dyn std::error::Error>> {
+        let mut db = CakeDb::new_temp()?;
+        let path = db.tempfile_path().unwrap().clone();
+        db.batch_insert(
+            TABLE,
+            (0..5_000).map(|i| (i, TestStruct::new(i, &"x".repeat(100)))),
+        )?;
+        db.savepoint()?;
+        db.clear_table(TABLE)?;
+        if gc {
+            db.clear_savepoints_and_gc()?;
+        } else {
+            db.clear_savepoints();
+        }
+        let cleared = std::fs::metadata(&path)?.len();
+        db.compact()?;
+        Ok((cleared, std::fs::metadata(&path)?.len()))
+    }
+
+    let (cleared_without_gc, compacted_without_gc) = file_sizes(false)?;
+    let (cleared_with_gc, compacted_with_gc) = file_sizes(true)?;
+    assert!(cleared_with_gc < cleared_without_gc);
+    assert!(compacted_with_gc <= compacted_without_gc);
+    Ok(())
+}
+
+#[test]
+fn unencodable_values_are_rejected_cleanly() -> Result<(), Box<dyn std::error::Error>> {
+    const UNENCODABLE_TABLE: TableDefinition<Bincode<u32>, Bincode<Unencodable>> =
+        TableDefinition::new("unencodable_table");
+    let mut db = CakeDb::new_temp()?;
+    db.batch_insert(UNENCODABLE_TABLE, (1..=3).map(|i| (i, Unencodable(i))))?;
+    let err = db
+        .insert(UNENCODABLE_TABLE, &4, Unencodable(0))
+        .unwrap_err();
+    assert!(err.to_string().contains("serialization error"));
+    let batch = [(4, Unencodable(4)), (5, Unencodable(0))];
+    assert!(db.batch_insert(UNENCODABLE_TABLE, batch).is_err());
+    assert_eq!(db.iter_keys(UNENCODABLE_TABLE)?.count(), 3);
+    Ok(())
+}
+
+#[test]
+fn multi_range_unions_ranges() -> Result<(), Box<dyn std::error::Error>> {
+    use std::ops::Bound::{Excluded, Included, Unbounded};
+
+    let mut db = CakeDb::new_temp()?;
+    db.batch_insert(TABLE, (1..=10).map(|i| (i, TestStruct::new(i, "n"))))?;
+    let ranges = [
+        (Included(2), Excluded(4)),
+        (Included(3), Included(4)),
+        (Excluded(8), Unbounded),
+    ];
+    let pairs = db.multi_range(TABLE, &ranges)?;
+    assert_eq!(
+        pairs.keys().copied().collect::<Vec<_>>(),
+        vec![2, 3, 4, 9, 10]
+    );
+    Ok(())
+}
+
+#[test]
+fn invert_groups_keys_by_value() -> Result<(), Box<dyn std::error::Error>> {
+    const CATEGORY_TABLE: TableDefinition<Bincode<u32>, Bincode<String>> =
+        TableDefinition::new("category_table");
+    let mut db = CakeDb::new_temp()?;
+    let categories = [(1, "fruit"), (2, "vegetable"), (3, "fruit"), (4, "grain")];
+    db.batch_insert(CATEGORY_TABLE, categories.map(|(k, v)| (k, v.to_string())))?;
+    let inverted = db.invert(CATEGORY_TABLE)?;
+    assert_eq!(inverted.len(), 3);
+    assert_eq!(inverted["fruit"], vec![1, 3]);
+    assert_eq!(inverted["vegetable"], vec![2]);
+    assert_eq!(inverted["grain"], vec![4]);
+    Ok(())
+}
+
+#[test]
+fn checkpoints_round_trip() -> Result<(), Box<dyn std::error::Error>> {
+    let dir = tempfile::tempdir()?;
+    let mut db = CakeDb::new_temp()?;
+    db.insert(TABLE, &1, TestStruct::new(1, "one"))?;
+    db.create_checkpoint("first", dir.path())?;
+    db.insert(TABLE, &2, TestStruct::new(2, "two"))?;
+    let second = db.create_checkpoint("second", dir.path())?;
+    assert!(second.exists());
+    db.insert(TABLE, &3, TestStruct::new(3, "three"))?;
+
+    assert_eq!(
+        CakeDb::list_checkpoints(dir.path())?,
+        vec!["first", "second"]
+    );
+    db.restore_checkpoint("first", dir.path())?;
+    assert_eq!(db.table(TABLE)?.into_keys().collect::<Vec<_>>(), vec![1]);
+    db.insert(TABLE, &4, TestStruct::new(4, "four"))?;
+    assert!(db.restore_checkpoint("missing", dir.path()).is_err());
+
+    // Keys past 250 don't sort by their encoded bytes, so they must be copied in stored order.
+    db.batch_insert(TABLE, (300..600).map(|i| (i, TestStruct::new(i, "bulk"))))?;
+    let bulk = db.table(TABLE)?;
+    db.create_checkpoint("bulk", dir.path())?;
+    db.clear_table(TABLE)?;
+    db.restore_checkpoint("bulk", dir.path())?;
+    assert_eq!(db.table(TABLE)?, bulk);
+    assert_eq!(db.get(TABLE, &450)?, Some(TestStruct::new(450, "bulk")));
+    Ok(())
+}
+
+#[test]
+fn checkpoint_names_cannot_escape_dir() -> Result<(), Box<dyn std::error::Error>> {
+    let dir = tempfile::tempdir()?;
+    let checkpoints = dir.path().join("checkpoints");
+    let db = CakeDb::new_temp()?;
+
+    for name in ["", "../escaped", "nested/name", "nested\\name", ".."] {
+        assert!(matches!(
+            db.create_checkpoint(name, &checkpoints),
+            Err(CakeError::InvalidArgument(_))
+        ));
+    }
+    assert!(!dir.path().join("escaped.redb").exists());
+    assert!(!checkpoints.exists());
+    Ok(())
+}
+
+#[test]
+fn map_keys_rekeys_table() -> Result<(), Box<dyn std::error::Error>> {
+    const SHIFTED_TABLE: TableDefinition<Bincode<u64>, Bincode<TestStruct>> =
+        TableDefinition::new("shifted_table");
+    let mut db = CakeDb::new_temp()?;
+    db.batch_insert(TABLE, (1..=3).map(|i| (i, TestStruct::new(i, "n"))))?;
+    assert_eq!(
+        db.map_keys(TABLE, SHIFTED_TABLE, |k| u64::from(*k) + 100)?,
+        3
+    );
+    let shifted = db.table(SHIFTED_TABLE)?;
+    assert_eq!(
+        shifted.keys().copied().collect::<Vec<_>>(),
+        vec![101, 102, 103]
+    );
+    assert_eq!(shifted[&102], TestStruct::new(2, "n"));
+    assert_eq!(db.table(TABLE)?.len(), 3);
+    Ok(())
+}
+
+#[test]
+fn read_transaction_gets_across_tables() -> Result<(), Box<dyn std::error::Error>> {
+    let mut db = CakeDb::new_temp()?;
+    db.insert(TABLE, &1, TestStruct::new(1, "one"))?;
+    db.insert(COMPLEX_TABLE, &1, ComplexRecord::new(1, "one", &["a"]))?;
+    let txn = db.read_transaction()?;
+    db.insert(TABLE, &1, TestStruct::new(10, "ten"))?;
+    assert_eq!(txn.get(TABLE, &1)?, Some(TestStruct::new(1, "one")));
+    assert_eq!(
+        txn.get(COMPLEX_TABLE, &1)?.map(|r| r.name),
+        Some("one".to_string())
+    );
+    assert_eq!(db.get(TABLE, &1)?, Some(TestStruct::new(10, "ten")));
+    Ok(())
+}
+
+#[test]
+fn prune_to_byte_budget_evicts_lowest_keys() -> Result<(), Box<dyn std::error::Error>> {
+    use redb::ReadableTableMetadata;
+
+    let mut db = CakeDb::new_temp()?;
+    db.batch_insert(
+        TABLE,
+        (0..100).map(|i| (i, TestStruct::new(i, &"x".repeat(50)))),
+    )?;
+    let removed = db.prune_to_byte_budget(TABLE, 1_000)?;
+    assert!(removed > 0);
+    let stored = db
+        .read_transaction()?
+        .open_table(TABLE)?
+        .stats()?
+        .stored_bytes();
+    assert!(stored <= 1_000);
+    assert_eq!(db.first_key(TABLE)?, Some(removed as u32));
+    assert_eq!(db.prune_to_byte_budget(TABLE, 1_000)?, 0);
+    Ok(())
+}
+
+#[test]
+fn insert_if_changed_skips_identical_values() -> Result<(), Box<dyn std::error::Error>> {
+    let mut db = CakeDb::new_temp()?;
+    assert!(db.insert_if_changed(TABLE, &1, TestStruct::new(1, "one"))?);
+    let revision = db.table_revision(TABLE)?;
+    assert!(!db.insert_if_changed(TABLE, &1, TestStruct::new(1, "one"))?);
+    assert_eq!(db.table_revision(TABLE)?, revision);
+    assert!(db.insert_if_changed(TABLE, &1, TestStruct::new(1, "uno"))?);
+    assert!(db.table_revision(TABLE)? > revision);
+    Ok(())
+}
+
+#[test]
+fn filter_into_other_collections() -> Result<(), Box<dyn std::error::Error>> {
+    use std::collections::HashMap;
+
+    let mut db = CakeDb::new_temp()?;
+    db.batch_insert(TABLE, (1..=5).map(|i| (i, TestStruct::new(i, "n"))))?;
+    let evens: Vec<(u32, TestStruct)> = db.filter_into(TABLE, |k, _| k % 2 == 0)?;
+    assert_eq!(
+        evens.iter().map(|(k, _)| *k).collect::<Vec<_>>(),
+        vec![2, 4]
+    );
+    let odds: HashMap<u32, TestStruct> = db.filter_into(TABLE, |k, _| k % 2 == 1)?;
+    assert_eq!(odds.len(), 3);
+    assert_eq!(odds[&5], TestStruct::new(5, "n"));
+    let all: Vec<(u32, TestStruct)> = db.collect_into(TABLE)?;
+    assert_eq!(all, db.table_vec_presized(TABLE)?);
+    Ok(())
+}
+
+#[test]
+fn savepoint_matches_current_after_undo() -> Result<(), Box<dyn std::error::Error>> {
+    let mut db = CakeDb::new_temp()?;
+    db.insert(TABLE, &1, TestStruct::new(1, "one"))?;
+    let save = db.savepoint()?;
+    assert!(db.savepoint_matches_current(save)?);
+    db.update(TABLE, &1, |v| v.b.push('!'))?;
+    assert!(!db.savepoint_matches_current(save)?);
+    db.update(TABLE, &1, |v| {
+        v.b.pop();
+    })?;
+    assert!(db.savepoint_matches_current(save)?);
+
+    // Tables created after the savepoint count as changes too, until they're emptied again.
+    let record = ComplexRecord::new(1, "one", &[]);
+    db.insert(COMPLEX_TABLE, &1, record.clone())?;
+    assert!(!db.savepoint_matches_current(save)?);
+    db.remove(COMPLEX_TABLE, &1)?;
+    assert!(db.savepoint_matches_current(save)?);
+    db.multimap_insert(MULTI_TABLE, &"k".to_string(), record)?;
+    assert!(!db.savepoint_matches_current(save)?);
+
+    assert!(matches!(
+        db.savepoint_matches_current(save + 1),
+        Err(CakeError::SavepointNotFound(_))
+    ));
+    Ok(())
+}
+
+#[test]
+fn iter_values_in_key_order() -> Result<(), Box<dyn std::error::Error>> {
+    let mut db = CakeDb::new_temp()?;
+    db.batch_insert(TABLE, [3, 1, 2].map(|i| (i, TestStruct::new(i * 10, "n"))))?;
+    let values = db.iter_values(TABLE)?.collect::<Result<Vec<_>, _>>()?;
+    assert_eq!(
+        values.iter().map(|v| v.a).collect::<Vec<_>>(),
+        vec![10, 20, 30]
+    );
+
+    let mut seen = Vec::new();
+    for value in db.iter_values(TABLE)? {
+        let value = value?;
+        if value.a > 10 {
+            break;
+        }
+        seen.push(value.a);
+    }
+    assert_eq!(seen, vec![10]);
+    Ok(())
+}
+
+#[test]
+fn close_then_rename_and_reopen() -> Result<(), Box<dyn std::error::Error>> {
+    let dir = tempfile::tempdir()?;
+    let mut db = CakeDb::new(dir.path().join("staging.redb"))?;
+    db.insert(TABLE, &1, TestStruct::new(1, "one"))?;
+    db.savepoint()?;
+    let path = db.close()?;
+
+    let deployed = dir.path().join("live.redb");
+    std::fs::rename(path, &deployed)?;
+    let db = CakeDb::new(&deployed)?;
+    assert_eq!(db.get(TABLE, &1)?, Some(TestStruct::new(1, "one")));
+    Ok(())
+}
+
+#[test]
+fn which_exist_reports_each_key() -> Result<(), Box<dyn std::error::Error>> {
+    let mut db = CakeDb::new_temp()?;
+    db.batch_insert(TABLE, [1, 3].map(|i| (i, TestStruct::new(i, "n"))))?;
+    let presence = db.which_exist(TABLE, [1, 2, 3, 4])?;
+    let expected = BTreeMap::from([(1, true), (2, false), (3, true), (4, false)]);
+    assert_eq!(presence, expected);
+    Ok(())
+}
+
+#[test]
+fn auto_savepoint_recovers_cleared_table() -> Result<(), Box<dyn std::error::Error>> {
+    let mut db = CakeDb::new_temp()?;
+    db.batch_insert(TABLE, (1..=3).map(|i| (i, TestStruct::new(i, "n"))))?;
+    assert_eq!(db.clear_table_safe(TABLE)?, None);
+    db.batch_insert(TABLE, (1..=3).map(|i| (i, TestStruct::new(i, "n"))))?;
+
+    db.set_auto_savepoint_on_destructive(true);
+    let save_key = db.clear_table_safe(TABLE)?.unwrap();
+    assert!(db.table(TABLE)?.is_empty());
+    db.load_savepoint(save_key)?;
+    assert_eq!(db.table(TABLE)?.len(), 3);
+
+    let (existed, save_key) = db.delete_table_safe(TABLE)?;
+    assert!(existed);
+    db.load_savepoint(save_key.unwrap())?;
+    assert_eq!(db.table(TABLE)?.len(), 3);
+    Ok(())
+}
+
+#[test]
+fn get_borrowed_with_str() -> Result<(), Box<dyn std::error::Error>> {
+    const NAMED_TABLE: TableDefinition<Bincode<String>, Bincode<TestStruct>> =
+        TableDefinition::new("named_table");
+    let mut db = CakeDb::new_temp()?;
+    db.insert(NAMED_TABLE, &"alice".to_string(), TestStruct::new(1, "a"))?;
+    assert_eq!(
+        db.get_borrowed(NAMED_TABLE, "alice")?,
+        Some(TestStruct::new(1, "a"))
+    );
+    assert_eq!(db.get_borrowed(NAMED_TABLE, "bob")?, None);
+    assert!(db.contains_key_borrowed(NAMED_TABLE, "alice")?);
+    assert!(!db.contains_key_borrowed(NAMED_TABLE, "bob")?);
+    Ok(())
+}
+
+#[test]
+fn table_hashmap_contains_all_entries() -> Result<(), Box<dyn std::error::Error>> {
+    let mut db = CakeDb::new_temp()?;
+    db.batch_insert(TABLE, (1..=4).map(|i| (i, TestStruct::new(i, "n"))))?;
+    let map = db.table_hashmap(TABLE)?;
+    assert_eq!(map.len(), 4);
+    for i in 1..=4 {
+        assert_eq!(map[&i], TestStruct::new(i, "n"));
+    }
+    Ok(())
+}
+
+#[test]
+fn verify_key_ordering_on_u32_keys() -> Result<(), Box<dyn std::error::Error>> {
+    let mut db = CakeDb::new_temp()?;
+    db.batch_insert(
+        TABLE,
+        [300, 2, 70_000, 1].map(|i| (i, TestStruct::new(i, "n"))),
+    )?;
+    assert!(db.verify_key_ordering(TABLE)?);
+    Ok(())
+}
+
+#[test]
+fn range_chunks_splits_range() -> Result<(), Box<dyn std::error::Error>> {
+    let mut db = CakeDb::new_temp()?;
+    db.batch_insert(TABLE, (1..=10).map(|i| (i, TestStruct::new(i, "n"))))?;
+    let chunks = db
+        .range_chunks(TABLE, 2..7, 2)?
+        .map(|chunk| chunk.map(|pairs| pairs.into_iter().map(|(k, _)| k).collect::<Vec<_>>()))
+        .collect::<Result<Vec<_>, _>>()?;
+    assert_eq!(chunks, vec![vec![2, 3], vec![4, 5], vec![6]]);
+    assert!(db.range_chunks(TABLE, .., 0).is_err());
+    Ok(())
+}
+
+#[test]
+fn with_value_reads_large_value_in_place() -> Result<(), Box<dyn std::error::Error>> {
+    let mut db = CakeDb::new_temp()?;
+    let tags: Vec<String> = (0..10_000).map(|i| format!("tag{i}")).collect();
+    let tags: Vec<&str> = tags.iter().map(String::as_str).collect();
+    db.insert(COMPLEX_TABLE, &1, ComplexRecord::new(1, "large", &tags))?;
+    let last_tag = db.with_value(COMPLEX_TABLE, &1, |r| r.tags.last().map(String::len))?;
+    assert_eq!(last_tag, Some(Some("tag9999".len())));
+    assert_eq!(db.with_value(COMPLEX_TABLE, &2, |r| r.id)?, None);
+    Ok(())
+}
+
+#[test]
+fn multimap_replace_all_returns_old_values() -> Result<(), Box<dyn std::error::Error>> {
+    let mut db = CakeDb::new_temp()?;
+    let key = "k1".to_string();
+    let record = |i| ComplexRecord::new(i, "r", &[]);
+    db.multimap_insert_values(MULTI_TABLE, &key, [2, 1].map(record))?;
+    let old = db.multimap_replace_all(MULTI_TABLE, &key, [3, 4].map(record))?;
+    assert_eq!(old, vec![record(1), record(2)]);
+    let expected: BTreeSet<_> = [3, 4].map(record).into();
+    assert_eq!(db.multimap_get(MULTI_TABLE, &key)?, expected);
+    Ok(())
+}
+
+#[test]
+fn iter_raw_round_trips() -> Result<(), Box<dyn std::error::Error>> {
+    let mut db = CakeDb::new_temp()?;
+    db.batch_insert(TABLE, (1..=3).map(|i| (i, TestStruct::new(i, "n"))))?;
+    let mut pairs = Vec::new();
+    for pair in db.iter_raw(TABLE)? {
+        let (key, value) = pair?;
+        pairs.push((
+            Bincode::<u32>::try_from_bytes(&key)?,
+            Bincode::<TestStruct>::try_from_bytes(&value)?,
+        ));
+    }
+    assert_eq!(pairs, db.table_vec_presized(TABLE)?);
+    assert_eq!(db.iter_raw(COMPLEX_TABLE)?.count(), 0);
+    Ok(())
+}
+
+#[test]
+fn read_raw_table_reads_any_table_by_name() -> Result<(), Box<dyn std::error::Error>> {
+    let mut db = CakeDb::new_temp()?;
+    db.batch_insert(TABLE, (1..=3).map(|i| (i, TestStruct::new(i, "n"))))?;
+    const FIXED: TableDefinition<u64, &str> = TableDefinition::new("fixed_width");
+    let write = db.database().begin_write()?;
+    write.open_table(FIXED)?.insert(7, "seven")?;
+    write.commit()?;
+
+    let raw: Vec<_> = db.iter_raw(TABLE)?.collect::<Result<_, _>>()?;
+    assert_eq!(db.read_raw_table("test_table")?, raw);
+    assert_eq!(
+        db.read_raw_table("fixed_width")?,
+        vec![(7u64.to_le_bytes().to_vec(), b"seven".to_vec())]
+    );
+    assert!(db.read_raw_table("missing").is_err());
+    Ok(())
+}
+
+#[test]
+fn export_savepoint_tables_to_another_db() -> Result<(), Box<dyn std::error::Error>> {
+    let mut source = CakeDb::new_temp()?;
+    source.batch_insert(TABLE, (1..=3).map(|i| (i, TestStruct::new(i, "n"))))?;
+    let save = source.savepoint()?;
+    source.clear_table(TABLE)?;
+    source.insert(COMPLEX_TABLE, &1, ComplexRecord::new(1, "one", &[]))?;
+    let later = source.savepoint()?;
+
+    let mut exported = Vec::new();
+    source.export_savepoint_tables(save, &["test_table", "complex_table"], &mut exported)?;
+    assert!(source.savepoints().contains_key(&later));
+    assert!(source.table(TABLE)?.is_empty());
+
+    let mut target = CakeDb::new_temp()?;
+    target.insert(COMPLEX_TABLE, &5, ComplexRecord::new(5, "five", &[]))?;
+    target.apply_snapshot(exported.as_slice())?;
+    assert_eq!(target.table(TABLE)?.len(), 3);
+    assert_eq!(target.get(TABLE, &2)?, Some(TestStruct::new(2, "n")));
+    // The table didn't exist at the savepoint, so it's removed.
+    assert_eq!(target.list_tables()?, vec!["test_table".to_string()]);
+    Ok(())
+}
+
+#[test]
+fn apply_snapshot_keeps_built_in_types() -> Result<(), Box<dyn std::error::Error>> {
+    let native: redb::TableDefinition<&str, u64> = redb::TableDefinition::new("native");
+    let mut source = CakeDb::new_temp()?;
+    let write = source.database().begin_write()?;
+    write.open_table(native)?.insert("a", 1)?;
+    write.commit()?;
+    let save = source.savepoint()?;
+
+    let mut exported = Vec::new();
+    source.export_savepoint_tables(save, &["native"], &mut exported)?;
+    let mut target = CakeDb::new_temp()?;
+    target.apply_snapshot(exported.as_slice())?;
+
+    let read = redb::ReadableDatabase::begin_read(target.database())?;
+    assert_eq!(
+        read.open_table(native)?.get("a")?.map(|v| v.value()),
+        Some(1)
+    );
+    Ok(())
+}
+
+#[cfg(feature = "json")]
+#[test]
+fn database_json_round_trip() -> Result<(), Box<dyn std::error::Error>> {
+    let mut source = CakeDb::new_temp()?;
+    source.batch_insert(TABLE, (1..=3).map(|i| (i, TestStruct::new(i, "n"))))?;
+    source.insert(
+        COMPLEX_TABLE,
+        &7,
+        ComplexRecord::new(7, "seven", &["a", "b"]),
+    )?;
+
+    let mut dump = Vec::new();
+    source.export_database_json(&mut dump)?;
+    let json: serde_json::Value = serde_json::from_slice(&dump)?;
+    assert_eq!(
+        json["test_table"]["entries"].as_array().map(Vec::len),
+        Some(3)
+    );
+    assert!(json.get("__cakedb_revisions").is_none());
+
+    let mut target = CakeDb::new_temp()?;
+    target.insert(TABLE, &9, TestStruct::new(9, "gone"))?;
+    target.import_database_json(dump.as_slice())?;
+    assert_eq!(target.table(TABLE)?, source.table(TABLE)?);
+    assert_eq!(target.table(COMPLEX_TABLE)?, source.table(COMPLEX_TABLE)?);
+    Ok(())
+}
+
+#[cfg(feature = "serde")]
+#[test]
+fn error_serializes_kind_and_message() -> Result<(), Box<dyn std::error::Error>> {
+    let json = serde_json::to_value(CakeError::KeyNotFound)?;
+    assert_eq!(
+        json,
+        serde_json::json!({ "kind": "KeyNotFound", "message": "key not found in table" })
+    );
+
+    let json = serde_json::to_value(CakeError::DuplicateInputKey("secret".to_string()))?;
+    assert_eq!(json["kind"], "DuplicateInputKey");
+    assert!(!json["message"]
+        .as_str()
+        .unwrap_or_default()
+        .contains("secret"));
+    Ok(())
+}
+
+#[cfg(feature = "rayon")]
+#[test]
+fn par_count_matches_equals_serial() -> Result<(), Box<dyn std::error::Error>> {
+    let mut db = CakeDb::new_temp()?;
+    db.batch_insert(TABLE, (0..5_000).map(|i| (i, TestStruct::new(i, "n"))))?;
+    let predicate = |k: &u32, v: &TestStruct| k.is_multiple_of(3) && v.a.is_multiple_of(2);
+    assert_eq!(
+        db.par_count_matches(TABLE, predicate)?,
+        db.count_matches(TABLE, predicate)?
+    );
+    assert_eq!(db.par_count_matches(COMPLEX_TABLE, |_, _| true)?, 0);
+    Ok(())
+}
+
+#[test]
+fn get_or_try_insert_with_caches_only_success() -> Result<(), Box<dyn std::error::Error>> {
+    let mut db = CakeDb::new_temp()?;
+    let failed = db.get_or_try_insert_with(TABLE, &1, || Err("producer failed"))?;
+    assert_eq!(failed, Err("producer failed"));
+    assert!(!db.contains_key(TABLE, &1)?);
+
+    let made = db.get_or_try_insert_with(TABLE, &1, || Ok::<_, &str>(TestStruct::new(1, "one")))?;
+    assert_eq!(made, Ok(TestStruct::new(1, "one")));
+    let cached = db.get_or_try_insert_with(TABLE, &1, || Err("not called"))?;
+    assert_eq!(cached, Ok(TestStruct::new(1, "one")));
+    Ok(())
+}
+
+#[test]
+fn multimap_get_page_paginates_values() -> Result<(), Box<dyn std::error::Error>> {
+    let mut db = CakeDb::new_temp()?;
+    let key = "k1".to_string();
+    let record = |i| ComplexRecord::new(i, "r", &[]);
+    db.multimap_insert_values(MULTI_TABLE, &key, (1..=5).map(record))?;
+
+    let mut pages = Vec::new();
+    let mut after = None;
+    loop {
+        let page = db.multimap_get_page(MULTI_TABLE, &key, after.as_ref(), 2)?;
+        let Some(last) = page.last().cloned() else {
+            break;
+        };
+        pages.push(page.iter().map(|r| r.id).collect::<Vec<_>>());
+        after = Some(last);
+    }
+    assert_eq!(pages, vec![vec![1, 2], vec![3, 4], vec![5]]);
+    Ok(())
+}
+
+#[test]
+fn batch_methods_accept_non_clone_keys() -> Result<(), Box<dyn std::error::Error>> {
+    const NO_CLONE_TABLE: TableDefinition<Bincode<NoCloneKey>, Bincode<u32>> =
+        TableDefinition::new("no_clone_table");
+    const NO_CLONE_MULTI: MultimapTableDefinition<Bincode<NoCloneKey>, Bincode<u32>> =
+        MultimapTableDefinition::new("no_clone_multimap");
+    let mut db = CakeDb::new_temp()?;
+    db.batch_insert(NO_CLONE_TABLE, (1..=3).map(|i| (NoCloneKey(i), i)))?;
+    let keys = [NoCloneKey(1), NoCloneKey(3)];
+    db.batch_update(NO_CLONE_TABLE, &keys, |_, v| *v *= 10)?;
+    assert_eq!(db.get(NO_CLONE_TABLE, &NoCloneKey(3))?, Some(30));
+    assert_eq!(db.get(NO_CLONE_TABLE, &NoCloneKey(2))?, Some(2));
+
+    db.multimap_batch_insert(
+        NO_CLONE_MULTI,
+        (1..=2).map(|i| (NoCloneKey(i), [i, i + 10])),
+    )?;
+    db.multimap_assign(NO_CLONE_MULTI, &NoCloneKey(1), [7])?;
+    db.multimap_insert_values(NO_CLONE_MULTI, &NoCloneKey(2), [8])?;
+    assert_eq!(
+        db.multimap_get(NO_CLONE_MULTI, &NoCloneKey(1))?,
+        BTreeSet::from([7])
+    );
+    assert_eq!(
+        db.multimap_get(NO_CLONE_MULTI, &NoCloneKey(2))?,
+        BTreeSet::from([2, 8, 12])
+    );
+    Ok(())
+}
+
+#[test]
+fn compact_names_savepoints_as_blocker() -> Result<(), Box<dyn std::error::Error>> {
+    let mut db = CakeDb::new_temp()?;
+    db.insert(TABLE, &1, TestStruct::new(1, "one"))?;
+    db.savepoint()?;
+    db.savepoint()?;
+
+    let err = db.compact().unwrap_err().to_string();
+    assert!(err.contains("2 active savepoints"), "{err}");
+    assert!(err.contains("clear_savepoints"), "{err}");
+
+    db.clear_savepoints();
+    db.compact()?;
+    Ok(())
+}
+
+#[test]
+fn range_inclusive_rejects_reversed_bounds() -> Result<(), Box<dyn std::error::Error>> {
+    let mut db = CakeDb::new_temp()?;
+    db.batch_insert(TABLE, (1..=5).map(|i| (i, TestStruct::new(i, "x"))))?;
+
+    let keys: Vec<u32> = db.range_inclusive(TABLE, 2, 4)?.into_keys().collect();
+    assert_eq!(keys, vec![2, 3, 4]);
+    assert_eq!(db.range_inclusive(TABLE, 3, 3)?.len(), 1);
+
+    let err = db.range_inclusive(TABLE, 4, 2).unwrap_err().to_string();
+    assert!(err.contains("invalid range"), "{err}");
+    Ok(())
+}
+
+#[test]
+fn maintain_compacts_fragmented_db() -> Result<(), Box<dyn std::error::Error>> {
+    let mut db = CakeDb::new_temp()?;
+    db.batch_insert(
+        TABLE,
+        (0..2000).map(|i| (i, TestStruct::new(i, &"x".repeat(64)))),
+    )?;
+    db.remove_where(TABLE, |k, _| !k.is_multiple_of(10))?;
+
+    let idle = db.maintain(None)?;
+    assert!(!idle.compacted);
+    assert_eq!(idle.passes, 0);
+    assert!(idle.stats_before.fragmentation_ratio() > 0.1);
+
+    let report = db.maintain(Some(0.1))?;
+    assert!(report.compacted);
+    assert!(report.passes >= 1);
+    assert!(report.stats_after.fragmented_bytes < report.stats_before.fragmented_bytes);
+    assert!(report.stats_after.fragmentation_ratio() < report.stats_before.fragmentation_ratio());
+    assert_eq!(db.table(TABLE)?.len(), 200);
+    Ok(())
+}
+
+#[test]
+fn try_update_reports_missing_key() -> Result<(), Box<dyn std::error::Error>> {
+    let mut db = CakeDb::new_temp()?;
+    db.insert(TABLE, &1, TestStruct::new(1, "one"))?;
+
+    assert!(db.try_update(TABLE, &1, |v| v.a = 10)?);
+    assert_eq!(db.get(TABLE, &1)?, Some(TestStruct::new(10, "one")));
+
+    assert!(!db.try_update(TABLE, &2, |v| v.a = 20)?);
+    assert_eq!(db.get(TABLE, &2)?, None);
+    Ok(())
+}
+
+#[test]
+fn multimap_remove_all_many_returns_per_key_values() -> Result<(), Box<dyn std::error::Error>> {
+    let mut db = CakeDb::new_temp()?;
+    let r1 = ComplexRecord::new(1, "one", &["a"]);
+    let r2 = ComplexRecord::new(2, "two", &["b"]);
+    let r3 = ComplexRecord::new(3, "three", &["c"]);
+    db.multimap_insert_values(MULTI_TABLE, &"a".to_string(), [r2.clone(), r1.clone()])?;
+    db.multimap_insert(MULTI_TABLE, &"b".to_string(), r3.clone())?;
+    db.multimap_insert(MULTI_TABLE, &"c".to_string(), r1.clone())?;
+
+    let removed = db.multimap_remove_all_many(
+        MULTI_TABLE,
+        ["a".to_string(), "b".to_string(), "missing".to_string()],
+    )?;
+    assert_eq!(removed["a"], vec![r1.clone(), r2]);
+    assert_eq!(removed["b"], vec![r3]);
+    assert!(removed["missing"].is_empty());
+
+    let remaining = db.multimap_table(MULTI_TABLE)?;
+    assert_eq!(remaining.keys().collect::<Vec<_>>(), vec!["c"]);
+    Ok(())
+}
+
+#[test]
+fn savepoint_count_tracks_savepoints() -> Result<(), Box<dyn std::error::Error>> {
+    let mut db = CakeDb::new_temp()?;
+    assert_eq!(db.savepoint_count(), 0);
+    assert!(!db.has_savepoints());
+
+    db.savepoint()?;
+    db.savepoint()?;
+    assert_eq!(db.savepoint_count(), 2);
+    assert!(db.has_savepoints());
+
+    db.clear_savepoints();
+    assert_eq!(db.savepoint_count(), 0);
+    assert!(!db.has_savepoints());
+    Ok(())
+}
+
+#[test]
+fn replace_or_default_swaps_counters() -> Result<(), Box<dyn std::error::Error>> {
+    const COUNTERS: TableDefinition<Bincode<String>, Bincode<u64>> =
+        TableDefinition::new("counters");
+    let mut db = CakeDb::new_temp()?;
+    let key = "hits".to_string();
+
+    assert_eq!(db.replace_or_default(COUNTERS, &key, 5)?, 0);
+    assert_eq!(db.replace_or_default(COUNTERS, &key, 0)?, 5);
+    assert_eq!(db.get(COUNTERS, &key)?, Some(0));
+    Ok(())
+}
+
+#[test]
+fn filter_sorted_by_orders_by_value_field() -> Result<(), Box<dyn std::error::Error>> {
+    let mut db = CakeDb::new_temp()?;
+    for (id, name) in [(1, "bravo"), (2, "delta"), (3, "alpha"), (4, "charlie")] {
+        db.insert(COMPLEX_TABLE, &id, ComplexRecord::new(id, name, &[]))?;
+    }
+
+    let sorted = db.filter_sorted_by(
+        COMPLEX_TABLE,
+        |id, _| *id != 2,
+        |(_, a), (_, b)| b.name.cmp(&a.name),
+    )?;
+    let names: Vec<&str> = sorted.iter().map(|(_, r)| r.name.as_str()).collect();
+    assert_eq!(names, vec!["charlie", "bravo", "alpha"]);
+    Ok(())
+}
+
+#[test]
+fn clone_to_forks_independent_copy() -> Result<(), Box<dyn std::error::Error>> {
+    let dir = tempfile::tempdir()?;
+    let mut db = CakeDb::new_temp()?;
+    db.insert(TABLE, &1, TestStruct::new(1, "one"))?;
+    let record = ComplexRecord::new(1, "one", &["a"]);
+    db.multimap_insert(MULTI_TABLE, &"k".to_string(), record.clone())?;
+
+    // Past 250, bincode's varint encoding no longer sorts like the numbers themselves,
+    // and length-prefixed strings don't sort like the strings.
+    db.batch_insert(TABLE, (300..600).map(|i| (i, TestStruct::new(i, "bulk"))))?;
+    for key in ["b", "aa", "ab", "c"] {
+        let records = (250..300).map(|i| ComplexRecord::new(i, key, &[]));
+        db.multimap_insert_values(MULTI_TABLE, &key.to_string(), records)?;
+    }
+    const FIXED: TableDefinition<u64, &str> = TableDefinition::new("fixed_width");
+    let write = db.database().begin_write()?;
+    write.open_table(FIXED)?.insert(7, "seven")?;
+    write.commit()?;
+
+    let mut clone = db.clone_to(dir.path().join("fork.redb"))?;
+    assert_eq!(clone.table(TABLE)?, db.table(TABLE)?);
+    assert_eq!(clone.first(TABLE)?.map(|(k, _)| k), Some(1));
+    assert_eq!(clone.get(TABLE, &450)?, Some(TestStruct::new(450, "bulk")));
+    assert_eq!(clone.table_revision(TABLE)?, db.table_revision(TABLE)?);
+    let read = redb::ReadableDatabase::begin_read(clone.database())?;
+    assert_eq!(
+        read.open_table(FIXED)?
+            .get(7)?
+            .map(|g| g.value().to_string()),
+        Some("seven".to_string())
+    );
+    drop(read);
+    assert!(db.clone_to(dir.path().join("fork.redb")).is_err());
+    assert_eq!(
+        clone.multimap_table(MULTI_TABLE)?,
+        db.multimap_table(MULTI_TABLE)?
+    );
+
+    clone.insert(TABLE, &2, TestStruct::new(2, "two"))?;
+    clone.update(TABLE, &1, |v| v.a = 10)?;
+    clone.multimap_remove_all(MULTI_TABLE, &"k".to_string())?;
+
+    assert_eq!(db.get(TABLE, &1)?, Some(TestStruct::new(1, "one")));
+    assert_eq!(db.get(TABLE, &2)?, None);
+    assert_eq!(
+        db.multimap_get(MULTI_TABLE, &"k".to_string())?,
+        BTreeSet::from([record])
+    );
+    Ok(())
+}
+
+#[test]
+fn get_if_skips_rejected_keys() -> Result<(), Box<dyn std::error::Error>> {
+    let mut db = CakeDb::new_temp()?;
+    db.insert(TABLE, &5, TestStruct::new(5, "five"))?;
+
+    let tenant = 0..10;
+    assert_eq!(
+        db.get_if(TABLE, &5, |k| tenant.contains(k))?,
+        Some(TestStruct::new(5, "five"))
+    );
+    assert_eq!(db.get_if(TABLE, &5, |k| !tenant.contains(k))?, None);
+
+    // A rejected key never opens the table, so it isn't created either.
+    assert_eq!(db.get_if(COMPLEX_TABLE, &50, |k| tenant.contains(k))?, None);
+    assert!(!db.list_tables()?.contains(&"complex_table".to_string()));
+    Ok(())
+}
+
+#[test]
+fn multimap_batch_update_edits_given_keys() -> Result<(), Box<dyn std::error::Error>> {
+    let mut db = CakeDb::new_temp()?;
+    let (a, b, c) = ("a".to_string(), "b".to_string(), "c".to_string());
+    db.multimap_insert_values(
+        MULTI_TABLE,
+        &a,
+        [
+            ComplexRecord::new(1, "one", &[]),
+            ComplexRecord::new(2, "two", &[]),
+        ],
+    )?;
+    db.multimap_insert(MULTI_TABLE, &b, ComplexRecord::new(3, "three", &[]))?;
+    db.multimap_insert(MULTI_TABLE, &c, ComplexRecord::new(4, "four", &[]))?;
+
+    let edited = db.multimap_batch_update(MULTI_TABLE, [a.clone(), b.clone()], |k, v| {
+        v.name = format!("{k}:{}", v.name)
+    })?;
+    assert_eq!(edited, 3);
+
+    let names = |key: &String| -> Result<Vec<String>, Box<dyn std::error::Error>> {
+        Ok(db
+            .multimap_get(MULTI_TABLE, key)?
+            .into_iter()
+            .map(|r| r.name)
+            .collect())
+    };
+    assert_eq!(names(&a)?, vec!["a:one", "a:two"]);
+    assert_eq!(names(&b)?, vec!["b:three"]);
+    assert_eq!(names(&c)?, vec!["four"]);
+    Ok(())
+}
+
+#[test]
+fn value_size_histogram_buckets_sizes() -> Result<(), Box<dyn std::error::Error>> {
+    const BLOBS: TableDefinition<Bincode<u32>, Bincode<Vec<u8>>> = TableDefinition::new("blobs");
+    let mut db = CakeDb::new_temp()?;
+    // Encoded sizes are the length plus a one-byte prefix below 251 bytes, and three bytes above.
+    for (key, len) in [(1, 0), (2, 9), (3, 99), (4, 127), (5, 999)] {
+        db.insert(BLOBS, &key, vec![0u8; len])?;
+    }
+
+    let histogram = db.value_size_histogram(BLOBS, &[128, 16, 4096])?;
+    assert_eq!(histogram, BTreeMap::from([(16, 2), (128, 2), (4096, 1)]));
+
+    let histogram = db.value_size_histogram(BLOBS, &[16])?;
+    assert_eq!(histogram, BTreeMap::from([(16, 2), (usize::MAX, 3)]));
+    Ok(())
+}
+
+#[test]
+fn on_commit_runs_only_after_commit() -> Result<(), Box<dyn std::error::Error>> {
+    let mut db = CakeDb::new_temp()?;
+    let fired = Rc::new(Cell::new(0));
+
+    let hook = fired.clone();
+    let failed = db.transaction(|txn| {
+        txn.insert(TABLE, &1, TestStruct::new(1, "one"))?;
+        txn.on_commit(move || hook.set(hook.get() + 1));
+        Err::<(), _>(CakeError::KeyNotFound)
+    });
+    assert!(failed.is_err());
+    assert_eq!(fired.get(), 0);
+    assert_eq!(db.get(TABLE, &1)?, None);
+
+    let hook = fired.clone();
+    db.transaction(|txn| {
+        txn.insert(TABLE, &1, TestStruct::new(1, "one"))?;
+        txn.insert(TABLE, &2, TestStruct::new(2, "two"))?;
+        txn.on_commit(move || hook.set(hook.get() + 1));
+        Ok(())
+    })?;
+    assert_eq!(fired.get(), 1);
+    assert_eq!(db.table(TABLE)?.len(), 2);
+    Ok(())
+}
+
+#[test]
+fn open_compacted_shrinks_fragmented_db() -> Result<(), Box<dyn std::error::Error>> {
+    let dir = tempfile::tempdir()?;
+    let path = dir.path().join("fragmented.redb");
+    {
+        let mut db = CakeDb::new(&path)?;
+        db.batch_insert(
+            TABLE,
+            (0..2000).map(|i| (i, TestStruct::new(i, &"x".repeat(64)))),
+        )?;
+        db.remove_where(TABLE, |k, _| !k.is_multiple_of(10))?;
+    }
+    let size_before = std::fs::metadata(&path)?.len();
+
+    let db = CakeDb::open_compacted(&path)?;
+    assert!(std::fs::metadata(&path)?.len() < size_before);
+    assert_eq!(db.table(TABLE)?.len(), 200);
+    assert_eq!(
+        db.get(TABLE, &10)?,
+        Some(TestStruct::new(10, &"x".repeat(64)))
+    );
+    Ok(())
+}
+
+#[test]
+fn bucket_counts_tallies_timestamps() -> Result<(), Box<dyn std::error::Error>> {
+    const EVENTS: TableDefinition<Bincode<i64>, Bincode<String>> = TableDefinition::new("events");
+    let mut db = CakeDb::new_temp()?;
+    for ts in [-5, 0, 3, 9, 10, 25, 29, 30, 100] {
+        db.insert(EVENTS, &ts, format!("event at {ts}"))?;
+    }
+
+    assert_eq!(db.bucket_counts(EVENTS, 0, 10, 3)?, vec![3, 1, 2]);
+    assert_eq!(db.bucket_counts(EVENTS, -10, 10, 2)?, vec![1, 3]);
+    assert_eq!(db.bucket_counts(EVENTS, 0, 50, 0)?, Vec::<u64>::new());
+    assert!(db.bucket_counts(EVENTS, 0, 0, 3).is_err());
+    Ok(())
+}
+
+#[test]
+fn table_budget_prunes_on_interval() -> Result<(), Box<dyn std::error::Error>> {
+    const BLOBS: TableDefinition<Bincode<u32>, Bincode<Vec<u8>>> = TableDefinition::new("blobs");
+    let mut db = CakeDb::new_temp()?;
+    // Each pair stores 102 bytes: a one-byte key, and 100 bytes plus a length prefix.
+    db.set_table_budget(BLOBS, 300, NonZeroU64::new(5).unwrap());
+
+    for key in 0..4 {
+        db.insert(BLOBS, &key, vec![0; 100])?;
+    }
+    assert_eq!(db.table(BLOBS)?.len(), 4);
+
+    db.insert(BLOBS, &4, vec![0; 100])?;
+    let keys: Vec<u32> = db.table(BLOBS)?.into_keys().collect();
+    assert_eq!(keys, vec![3, 4]);
+
+    db.set_table_budget(BLOBS, 300, NonZeroU64::MIN);
+    db.batch_insert(BLOBS, (5..10).map(|key| (key, vec![0; 100])))?;
+    let keys: Vec<u32> = db.table(BLOBS)?.into_keys().collect();
+    assert_eq!(keys, vec![8, 9]);
+    Ok(())
+}
+
+#[test]
+fn range_columns_aligns_keys_and_values() -> Result<(), Box<dyn std::error::Error>> {
+    let mut db = CakeDb::new_temp()?;
+    db.batch_insert(TABLE, (1..=6).map(|i| (i, TestStruct::new(i * 10, "x"))))?;
+
+    let (keys, values) = db.range_columns(TABLE, 2..5)?;
+    assert_eq!(keys, vec![2, 3, 4]);
+    assert_eq!(
+        values.iter().map(|v| v.a).collect::<Vec<_>>(),
+        vec![20, 30, 40]
+    );
+    Ok(())
+}
+
+#[test]
+fn changed_keys_since_savepoint_lists_edits() -> Result<(), Box<dyn std::error::Error>> {
+    let mut db = CakeDb::new_temp()?;
+    db.batch_insert(TABLE, (1..=4).map(|i| (i, TestStruct::new(i, "x"))))?;
+    let save = db.savepoint()?;
+    assert!(db.changed_keys_since_savepoint(save, TABLE)?.is_empty());
+
+    db.insert(TABLE, &6, TestStruct::new(6, "new"))?;
+    db.update(TABLE, &2, |v| v.b.push('!'))?;
+    db.remove(TABLE, &3)?;
+    db.insert(TABLE, &4, TestStruct::new(4, "x"))?;
+
+    assert_eq!(db.changed_keys_since_savepoint(save, TABLE)?, vec![2, 3, 6]);
+    assert_eq!(db.get(TABLE, &6)?, Some(TestStruct::new(6, "new")));
+    Ok(())
+}
+
+#[test]
+fn scan_yields_pairs_lazily() -> Result<(), Box<dyn std::error::Error>> {
+    let mut db = CakeDb::new_temp()?;
+    db.batch_insert(TABLE, (0..1000).map(|i| (i, TestStruct::new(i * 2, "x"))))?;
+
+    let head = db.scan(TABLE)?.take(100).collect::<Result<Vec<_>, _>>()?;
+    assert_eq!(head.len(), 100);
+    assert_eq!(head[99], (99, TestStruct::new(198, "x")));
+
+    let mut scanned = 0;
+    for pair in db.scan(TABLE)? {
+        let (k, v) = pair?;
+        assert_eq!(v.a, k * 2);
+        scanned += 1;
+    }
+    assert_eq!(scanned, 1000);
+    Ok(())
+}
+
+#[test]
+fn batch_replace_returns_overwritten_values() -> Result<(), Box<dyn std::error::Error>> {
+    let mut db = CakeDb::new_temp()?;
+    db.batch_insert(TABLE, (1..=3).map(|i| (i, TestStruct::new(i, "old"))))?;
+
+    let replaced = db.batch_replace(
+        TABLE,
+        vec![
+            (2, TestStruct::new(20, "new")),
+            (4, TestStruct::new(40, "new")),
+            (3, TestStruct::new(30, "new")),
+            (4, TestStruct::new(41, "newer")),
+        ],
+    )?;
+    assert_eq!(
+        replaced,
+        BTreeMap::from([
+            (2, TestStruct::new(2, "old")),
+            (3, TestStruct::new(3, "old"))
+        ])
+    );
+    assert_eq!(db.get(TABLE, &4)?, Some(TestStruct::new(41, "newer")));
+    assert_eq!(db.get(TABLE, &1)?, Some(TestStruct::new(1, "old")));
+    Ok(())
+}
+
+#[test]
+fn keys_and_values_skip_the_other_half() -> Result<(), Box<dyn std::error::Error>> {
+    let mut db = CakeDb::new_temp()?;
+    db.batch_insert(TABLE, [3, 1, 2].map(|i| (i, TestStruct::new(i * 10, "x"))))?;
+
+    assert_eq!(db.keys(TABLE)?, vec![1, 2, 3]);
+    let values: Vec<u32> = db.values(TABLE)?.into_iter().map(|v| v.a).collect();
+    assert_eq!(values, vec![10, 20, 30]);
+
+    // Values that can't be decoded don't matter when only keys are read.
+    db.batch_insert(UNDECODABLE_TABLE, (1..=2).map(|i| (i, Undecodable(i))))?;
+    assert_eq!(db.keys(UNDECODABLE_TABLE)?, vec![1, 2]);
+    Ok(())
+}
+
+#[test]
+fn try_compact_skips_instead_of_failing() -> Result<(), Box<dyn std::error::Error>> {
+    let mut db = CakeDb::new_temp()?;
+    db.batch_insert(
+        TABLE,
+        (0..500).map(|i| (i, TestStruct::new(i, &"x".repeat(64)))),
+    )?;
+    db.remove_where(TABLE, |k, _| !k.is_multiple_of(10))?;
+    db.savepoint()?;
+
+    assert_eq!(db.try_compact()?, CompactOutcome::SkippedSavepointsActive);
+
+    db.clear_savepoints();
+    assert!(matches!(db.try_compact()?, CompactOutcome::Compacted(_)));
+    Ok(())
+}
+
+#[test]
+fn count_reads_length_without_decoding() -> Result<(), Box<dyn std::error::Error>> {
+    let mut db = CakeDb::new_temp()?;
+    assert_eq!(db.count(TABLE)?, 0);
+    assert!(db.is_empty(TABLE)?);
+    assert!(db.list_tables()?.is_empty());
+
+    db.batch_insert(TABLE, (0..25).map(|i| (i, TestStruct::new(i, "x"))))?;
+    assert_eq!(db.count(TABLE)?, 25);
+    assert!(!db.is_empty(TABLE)?);
+
+    db.batch_insert(UNDECODABLE_TABLE, (0..3).map(|i| (i, Undecodable(i))))?;
+    assert_eq!(db.count(UNDECODABLE_TABLE)?, 3);
+    Ok(())
+}